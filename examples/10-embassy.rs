@@ -0,0 +1,132 @@
+//! Echo back data over the serial line in packs of eight (@ 9_600 bauds) while running the heartbeat task, using `embassy-executor`
+//!
+//! The futures of this crate are plain futures, so they run on any executor.
+//! With the `user-interrupts` feature, this crate does not declare its interrupts, so they don't conflict with interrupts used by the executor.
+//! Instead, the handlers of the interrupts used by this crate are declared below and forwarded via `isr::on_interrupt`.
+//! The same applies to an `InterruptExecutor` of `embassy-executor`, which should run on an interrupt not used by this crate, such as `SPI1`.
+//!
+//! This assumes that a LED is connected to pc13 as is the case on the blue pill board.
+
+#![no_main]
+#![no_std]
+
+use async_stm32f1xx::{
+    isr,
+    serial::{RxStream3, TxSink3},
+    timer::AsyncTimer,
+};
+use cortex_m_07 as _; // critical section
+use cortex_m_rt::entry;
+use defmt_rtt as _; // global logger
+use embassy_executor::{Executor, Spawner};
+use futures::sink::SinkExt;
+use panic_probe as _; // panic handler
+use stm32f1xx_hal::{
+    dma,
+    gpio::{gpioc::PC13, Output, PushPull, State},
+    pac::{interrupt, Interrupt, Peripherals, TIM2},
+    prelude::*,
+    serial::{Config, Serial},
+    timer::{CountDownTimer, Timer},
+};
+
+#[interrupt]
+fn TIM2() {
+    isr::on_interrupt(Interrupt::TIM2);
+}
+
+#[interrupt]
+fn DMA1_CHANNEL2() {
+    isr::on_interrupt(Interrupt::DMA1_CHANNEL2);
+}
+
+#[interrupt]
+fn DMA1_CHANNEL3() {
+    isr::on_interrupt(Interrupt::DMA1_CHANNEL3);
+}
+
+#[embassy_executor::task]
+async fn heartbeat(mut timer: AsyncTimer<CountDownTimer<TIM2>>, mut led: PC13<Output<PushPull>>) {
+    loop {
+        led.toggle().unwrap();
+        timer.delay_for(10.hz()).await.unwrap();
+        led.toggle().unwrap();
+        timer.delay_for(4.hz()).await.unwrap();
+        led.toggle().unwrap();
+        timer.delay_for(10.hz()).await.unwrap();
+        led.toggle().unwrap();
+        timer.delay_for(2.hz()).await.unwrap();
+    }
+}
+
+#[embassy_executor::task]
+async fn echo(tx_sink: TxSink3<'static, [u8; 8]>, mut rx_stream: RxStream3<[u8; 8]>) {
+    let mut tx_sink = tx_sink.sink_map_err(|_| dma::Error::Overrun);
+    tx_sink.send_all(&mut rx_stream).await.unwrap();
+    unreachable!("rx_stream is empty");
+}
+
+#[entry]
+fn main() -> ! {
+    // Extract needed peripherals
+    let dp = Peripherals::take().unwrap();
+
+    // Avoid AHB going into low-power mode causing RTT to stop working
+    dp.RCC.ahbenr.modify(|_, w| w.dma1en().enabled());
+
+    let rcc = dp.RCC.constrain();
+    let mut apb1 = rcc.apb1;
+    let mut acr = dp.FLASH.constrain().acr;
+    let clocks = rcc.cfgr.freeze(&mut acr);
+
+    // Create Timer
+    let timer: AsyncTimer<_> = Timer::tim2(dp.TIM2, &clocks, &mut apb1).into();
+
+    // Create Led
+    let mut apb2 = rcc.apb2;
+    let gpioc = dp.GPIOC.split(&mut apb2);
+    let mut cr = gpioc.crh;
+    let led = gpioc
+        .pc13
+        .into_push_pull_output_with_state(&mut cr, State::High);
+
+    // Create TxSink and RxStream
+    let mut gpiob = dp.GPIOB.split(&mut apb2);
+    let tx = gpiob.pb10.into_alternate_push_pull(&mut gpiob.crh);
+    let rx = gpiob.pb11;
+    let afio = dp.AFIO.constrain(&mut apb2);
+    let mut mapr = afio.mapr;
+    let serial = Serial::usart3(
+        dp.USART3,
+        (tx, rx),
+        &mut mapr,
+        Config::default().baudrate(9_600.bps()),
+        clocks,
+        &mut apb1,
+    );
+    let mut ahb = rcc.ahb;
+    let channels = dp.DMA1.split(&mut ahb);
+    let (tx, rx) = serial.split();
+    let tx_buf = {
+        static mut BUF: [u8; 8] = [0; 8];
+        // Safety: We only create one mutable reference
+        unsafe { &mut BUF }
+    };
+    let tx_sink = TxSink3::new(tx_buf, tx.with_dma(channels.2));
+    let rx_buf = {
+        static mut BUF: [[u8; 8]; 2] = [[0; 8]; 2];
+        // Safety: We only create one mutable reference
+        unsafe { &mut BUF }
+    };
+    let rx_stream = RxStream3::new(rx_buf, rx.with_dma(channels.3));
+
+    let executor = {
+        static mut EXECUTOR: Option<Executor> = None;
+        // Safety: We only create one mutable reference
+        unsafe { EXECUTOR.insert(Executor::new()) }
+    };
+    executor.run(|spawner: Spawner| {
+        spawner.spawn(heartbeat(timer, led)).unwrap();
+        spawner.spawn(echo(tx_sink, rx_stream)).unwrap();
+    })
+}
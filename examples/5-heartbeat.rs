@@ -6,11 +6,16 @@
 #![no_std]
 
 use async_embedded::task;
-use async_stm32f1xx::timer::AsyncTimer;
+use async_stm32f1xx::timer::{AsyncTimer, Hz, Instance, Ticks};
 use cortex_m_rt::entry;
 use defmt_rtt as _; // global logger
 use panic_probe as _; // panic handler
-use stm32f1xx_hal::{gpio::State, pac::Peripherals, prelude::*, timer::Timer};
+use stm32f1xx_hal::{
+    gpio::State,
+    pac::{Peripherals, TIM2},
+    prelude::*,
+    timer::{CountDownTimer, Timer},
+};
 
 #[entry]
 fn main() -> ! {
@@ -36,16 +41,12 @@ fn main() -> ! {
         .pc13
         .into_push_pull_output_with_state(&mut cr, State::High);
 
+    // Create heartbeat pattern
+    let clock = CountDownTimer::<TIM2>::clock(&clocks);
+    let pattern = [10, 4, 10, 2].map(|freq| Ticks::from_hz(Hz(freq), clock));
+
     task::block_on(async {
-        loop {
-            led.toggle().unwrap();
-            timer.delay_for(10.hz()).await;
-            led.toggle().unwrap();
-            timer.delay_for(4.hz()).await;
-            led.toggle().unwrap();
-            timer.delay_for(10.hz()).await;
-            led.toggle().unwrap();
-            timer.delay_for(2.hz()).await;
-        }
+        let res = timer.blink_pattern(&mut led, &pattern).await;
+        match res.unwrap() {}
     })
 }
@@ -51,13 +51,13 @@ fn main() -> ! {
     task::spawn(async move {
         loop {
             led.toggle().unwrap();
-            timer.delay_for(10.hz()).await;
+            timer.delay_for(10.hz()).await.unwrap();
             led.toggle().unwrap();
-            timer.delay_for(4.hz()).await;
+            timer.delay_for(4.hz()).await.unwrap();
             led.toggle().unwrap();
-            timer.delay_for(10.hz()).await;
+            timer.delay_for(10.hz()).await.unwrap();
             led.toggle().unwrap();
-            timer.delay_for(2.hz()).await;
+            timer.delay_for(2.hz()).await.unwrap();
         }
     });
 
@@ -0,0 +1,69 @@
+//! Starting a delay after a cancelled one
+//!
+//! A cancelled delay must not leave an update event behind, which would make the next delay resolve immediately.
+//! This checks the length of such a delay using the cycle counter.
+//!
+//! # Expected output
+//!
+//! ```
+//! The delay after the cancelled one has lasted 100 ms.
+//! ```
+
+#![no_main]
+#![no_std]
+
+use async_embedded::task;
+use async_stm32f1xx::timer::AsyncTimer;
+use cortex_m::{asm, peripheral::DWT};
+use cortex_m_rt::entry;
+use defmt::info;
+use defmt_rtt as _; // global logger
+use panic_probe as _; // panic handler
+use stm32f1xx_hal::{
+    pac::{CorePeripherals, Peripherals},
+    prelude::*,
+    timer::Timer,
+};
+
+#[entry]
+fn main() -> ! {
+    // Extract needed peripherals
+    let dp = Peripherals::take().expect("Peripherals have been taken before");
+    let mut cp = CorePeripherals::take().expect("CorePeripherals have been taken before");
+
+    // Avoid AHB going into low-power mode causing RTT to stop working
+    dp.RCC.ahbenr.modify(|_, w| w.dma1en().enabled());
+
+    let rcc = dp.RCC.constrain();
+
+    // Create Timer
+    let mut apb1 = rcc.apb1;
+    let mut acr = dp.FLASH.constrain().acr;
+    let clocks = rcc.cfgr.freeze(&mut acr);
+    let mut timer: AsyncTimer<_> = Timer::tim2(dp.TIM2, &clocks, &mut apb1).into();
+
+    // Enable the cycle counter
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    task::block_on(async {
+        // Cancel a delay and wait for longer than its period
+        drop(timer.delay_for(10.hz()));
+        asm::delay(clocks.sysclk().0 / 5);
+
+        let start = DWT::get_cycle_count();
+        timer.delay_for(10.hz()).await.unwrap();
+        let elapsed = DWT::get_cycle_count().wrapping_sub(start);
+
+        let ms = elapsed / (clocks.sysclk().0 / 1_000);
+        assert!(ms >= 99, "the delay has resolved early");
+        info!(
+            "The delay after the cancelled one has lasted {:u32} ms.",
+            ms
+        );
+    });
+
+    loop {
+        asm::wfi();
+    }
+}
@@ -0,0 +1,73 @@
+//! Blinking an LED in a heartbeat rhythm with an application-declared TIM2 interrupt handler
+//!
+//! With the `user-interrupts` feature, this crate does not declare its interrupts.
+//! The application declares them instead and forwards them to the crate, e.g. for running under `embassy-executor`.
+//!
+//! This assumes that a LED is connected to pc13 as is the case on the blue pill board.
+
+#![no_main]
+#![no_std]
+
+use async_embedded::task;
+use async_stm32f1xx::{isr, timer::AsyncTimer};
+use core::sync::atomic::{AtomicU32, Ordering};
+use cortex_m_rt::entry;
+use defmt::info;
+use defmt_rtt as _; // global logger
+use panic_probe as _; // panic handler
+use stm32f1xx_hal::{
+    gpio::State,
+    pac::{interrupt, Interrupt, Peripherals},
+    prelude::*,
+    timer::Timer,
+};
+
+static TIM2_COUNT: AtomicU32 = AtomicU32::new(0);
+
+#[interrupt]
+fn TIM2() {
+    TIM2_COUNT.fetch_add(1, Ordering::Relaxed);
+    isr::on_interrupt(Interrupt::TIM2);
+}
+
+#[entry]
+fn main() -> ! {
+    // Extract needed peripherals
+    let dp = Peripherals::take().expect("Peripherals have been taken before");
+
+    // Avoid AHB going into low-power mode causing RTT to stop working
+    dp.RCC.ahbenr.modify(|_, w| w.dma1en().enabled());
+
+    let rcc = dp.RCC.constrain();
+
+    // Create Timer
+    let mut apb1 = rcc.apb1;
+    let mut acr = dp.FLASH.constrain().acr;
+    let clocks = rcc.cfgr.freeze(&mut acr);
+    let mut timer: AsyncTimer<_> = Timer::tim2(dp.TIM2, &clocks, &mut apb1).into();
+
+    // Create Led
+    let mut apb2 = rcc.apb2;
+    let gpioc = dp.GPIOC.split(&mut apb2);
+    let mut cr = gpioc.crh;
+    let mut led = gpioc
+        .pc13
+        .into_push_pull_output_with_state(&mut cr, State::High);
+
+    task::block_on(async {
+        loop {
+            led.toggle().unwrap();
+            timer.delay_for(10.hz()).await.unwrap();
+            led.toggle().unwrap();
+            timer.delay_for(4.hz()).await.unwrap();
+            led.toggle().unwrap();
+            timer.delay_for(10.hz()).await.unwrap();
+            led.toggle().unwrap();
+            timer.delay_for(2.hz()).await.unwrap();
+            info!(
+                "TIM2 has been handled {:u32} times.",
+                TIM2_COUNT.load(Ordering::Relaxed)
+            );
+        }
+    })
+}
@@ -0,0 +1,201 @@
+//! Fan-out of a [`Stream`] to several consumers.
+//!
+//! Each item of the source stream, such as an [`RxStream`], is cloned into a bounded queue per consumer.
+//! The consumers, e.g. a logger and a parser task, receive the items from their queues via [`Subscriber`]s.
+//! The source is polled by whichever subscriber runs out of items, so no additional task is needed.
+//!
+//! # Examples
+//!
+//! ```
+//! static mut LOGGER: Queue<[u8; 8], 4> = Queue::new();
+//! static mut PARSER: Queue<[u8; 8], 4> = Queue::new();
+//!
+//! let rx_stream = rx_stream.map(Result::unwrap);
+//! let broadcast = rx_stream.broadcast(unsafe { [&mut LOGGER, &mut PARSER] }, Overflow::Drop);
+//! let logger = broadcast.subscribe(0);
+//! let parser = broadcast.subscribe(1);
+//! ```
+//!
+//! [`RxStream`]: crate::serial::RxStream
+
+use core::{
+    cell::RefCell,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use futures::stream::{FusedStream, Stream};
+use heapless::spsc::Queue;
+
+/// Specifies the behavior of a [`Broadcast`] when the queue of a consumer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the item for the consumer with a full queue, counting it in [`Broadcast::dropped`].
+    Drop,
+    /// Stop polling the source until all queues have space.
+    Backpressure,
+}
+
+/// An extension trait for [`Stream`]s that provides broadcasting to several consumers.
+pub trait BroadcastExt: Stream + Sized {
+    /// Creates a [`Broadcast`] cloning each item into each of `queues`.
+    fn broadcast<const N: usize, const C: usize>(
+        self,
+        queues: [&mut Queue<Self::Item, N>; C],
+        overflow: Overflow,
+    ) -> Broadcast<'_, Self, N, C> {
+        Broadcast(RefCell::new(Inner {
+            stream: self,
+            queues,
+            wakers: [(); C].map(|()| None),
+            dropped: [0; C],
+            overflow,
+            terminated: false,
+        }))
+    }
+}
+
+impl<S: Stream> BroadcastExt for S {}
+
+/// A [`Stream`] broadcasted to several [`Subscriber`]s.
+///
+/// This `struct` is created by the `broadcast` method on [`BroadcastExt`].
+pub struct Broadcast<'a, S: Stream, const N: usize, const C: usize>(RefCell<Inner<'a, S, N, C>>);
+
+struct Inner<'a, S: Stream, const N: usize, const C: usize> {
+    stream: S,
+    queues: [&'a mut Queue<S::Item, N>; C],
+    wakers: [Option<Waker>; C],
+    dropped: [u32; C],
+    overflow: Overflow,
+    terminated: bool,
+}
+
+impl<S: Stream, const N: usize, const C: usize> Inner<'_, S, N, C> {
+    /// Wakes all consumers but `index`.
+    fn wake_others(&mut self, index: usize) {
+        for (i, waker) in self.wakers.iter_mut().enumerate() {
+            if i != index {
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S: Stream, const N: usize, const C: usize> Broadcast<'a, S, N, C> {
+    /// Creates a [`Subscriber`] receiving the items from the queue at `index`.
+    ///
+    /// Each index should only be subscribed once, as subscribers of the same index split the items between them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn subscribe(&self, index: usize) -> Subscriber<'_, 'a, S, N, C> {
+        assert!(index < C, "index out of bounds");
+        Subscriber {
+            broadcast: self,
+            index,
+        }
+    }
+
+    /// Returns the number of items dropped for the consumer at `index`, because its queue was full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn dropped(&self, index: usize) -> u32 {
+        self.0.borrow().dropped[index]
+    }
+
+    /// Releases the source stream.
+    pub fn into_inner(self) -> S {
+        self.0.into_inner().stream
+    }
+}
+
+/// A [`Stream`] yielding the items of a [`Broadcast`] for one consumer.
+///
+/// This `struct` is created by the `subscribe` method on [`Broadcast`].
+#[must_use = "streams do nothing unless polled"]
+pub struct Subscriber<'b, 'a, S: Stream, const N: usize, const C: usize> {
+    broadcast: &'b Broadcast<'a, S, N, C>,
+    index: usize,
+}
+
+impl<S, const N: usize, const C: usize> Subscriber<'_, '_, S, N, C>
+where
+    S: Stream,
+{
+    /// Returns the number of items dropped for this consumer, because its queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.broadcast.dropped(self.index)
+    }
+}
+
+impl<S, const N: usize, const C: usize> Stream for Subscriber<'_, '_, S, N, C>
+where
+    S: Stream + Unpin,
+    S::Item: Clone,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let index = self.index;
+        let mut inner = self.broadcast.0.borrow_mut();
+        let inner = &mut *inner;
+        loop {
+            if let Some(item) = inner.queues[index].dequeue() {
+                if inner.overflow == Overflow::Backpressure {
+                    // A consumer may be waiting for this space
+                    inner.wake_others(index);
+                }
+                return Poll::Ready(Some(item));
+            }
+
+            if inner.terminated {
+                return Poll::Ready(None);
+            }
+
+            let full = inner
+                .queues
+                .iter()
+                .any(|queue| queue.len() == queue.capacity());
+            if inner.overflow == Overflow::Backpressure && full {
+                inner.wakers[index] = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+
+            // The source only wakes the consumer polling it last, which wakes the others when distributing
+            match Pin::new(&mut inner.stream).poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    for (queue, dropped) in inner.queues.iter_mut().zip(inner.dropped.iter_mut()) {
+                        if queue.enqueue(item.clone()).is_err() {
+                            *dropped += 1;
+                        }
+                    }
+                    inner.wake_others(index);
+                }
+                Poll::Ready(None) => {
+                    inner.terminated = true;
+                    inner.wake_others(index);
+                }
+                Poll::Pending => {
+                    inner.wakers[index] = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+impl<S, const N: usize, const C: usize> FusedStream for Subscriber<'_, '_, S, N, C>
+where
+    S: Stream + Unpin,
+    S::Item: Clone,
+{
+    fn is_terminated(&self) -> bool {
+        let inner = self.broadcast.0.borrow();
+        inner.terminated && inner.queues[self.index].is_empty()
+    }
+}
@@ -0,0 +1,74 @@
+//! [`Future`]-based DMA memory-to-memory transfers and sharing of DMA channels.
+//!
+//! [`memcpy`] requires the `memcpy` feature, which implements the [`DMA1_CHANNEL1`](stm32f1xx_hal::pac::Interrupt::DMA1_CHANNEL1) interrupt.
+//!
+//! [`Future`]: core::future::Future
+
+use crate::sync::{Mutex, MutexGuard};
+
+#[cfg(feature = "memcpy")]
+mod memcpy;
+#[cfg(feature = "memcpy")]
+pub use self::memcpy::{memcpy, Memcpy, Word};
+
+/// A DMA channel shared between peripherals, which lease it for the duration of their transfers.
+///
+/// The lease is an asynchronous mutex, so a second peripheral waits until the channel has been returned.
+///
+/// # Examples
+///
+/// ```
+/// static mut CHANNEL1: DmaChannelLease<C1> = DmaChannelLease::new();
+/// // In each task
+/// let mut lease = CHANNEL1.lease().await;
+/// let (channel, src, dst) = dma::memcpy(lease.take(), src, dst).await;
+/// lease.restore(channel);
+/// ```
+pub struct DmaChannelLease<C>(Mutex<Option<C>>);
+
+impl<C> DmaChannelLease<C> {
+    /// Creates a new [`DmaChannelLease`] without a channel.
+    ///
+    /// The channel is provided by the first lease via [`Lease::restore`].
+    pub const fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+
+    /// Creates a new [`DmaChannelLease`] from the specified channel.
+    pub const fn with_channel(channel: C) -> Self {
+        Self(Mutex::new(Some(channel)))
+    }
+
+    /// Leases the channel, waiting until it is available.
+    pub async fn lease(&self) -> Lease<'_, C> {
+        Lease(self.0.lock().await)
+    }
+
+    /// Releases the channel, if it has been restored.
+    pub fn into_inner(self) -> Option<C> {
+        self.0.into_inner()
+    }
+}
+
+/// A lease of the channel of a [`DmaChannelLease`].
+///
+/// The lease ends when this is dropped.
+/// If the channel has been taken, it has to be restored before, as the channel is missing for the next lease otherwise.
+#[must_use = "if unused the lease will immediately end"]
+pub struct Lease<'a, C>(MutexGuard<'a, Option<C>>);
+
+impl<C> Lease<'_, C> {
+    /// Takes the channel for starting a transfer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the channel has not been restored by a previous lease.
+    pub fn take(&mut self) -> C {
+        self.0.take().expect("channel has not been restored")
+    }
+
+    /// Restores the channel after a transfer has completed.
+    pub fn restore(&mut self, channel: C) {
+        *self.0 = Some(channel);
+    }
+}
@@ -0,0 +1,156 @@
+//! DMA memory-to-memory transfers.
+
+use core::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use embedded_dma::{StaticReadBuffer, StaticWriteBuffer};
+use futures::future::FusedFuture;
+use stm32f1xx_hal::{
+    dma::{dma1::C1, Event},
+    pac::{dma1::ch::cr, DMA1},
+};
+
+mod sealed {
+    pub trait Word {}
+}
+
+/// A word size supported by the DMA, i.e., [`u8`], [`u16`] or [`u32`].
+pub trait Word: sealed::Word {
+    #[doc(hidden)]
+    fn configure(w: &mut cr::W) -> &mut cr::W;
+}
+
+macro_rules! word {
+    ($($T:ty: $bitsX:ident,)+) => {
+        $(
+            impl sealed::Word for $T {}
+
+            impl Word for $T {
+                fn configure(w: &mut cr::W) -> &mut cr::W {
+                    w.msize().$bitsX().psize().$bitsX()
+                }
+            }
+        )+
+    }
+}
+
+word!(
+    u8: bits8,
+    u16: bits16,
+    u32: bits32,
+);
+
+/// Asserts that `ptr` is aligned for DMA transfers of `W`, if debug assertions are enabled.
+///
+/// The DMA ignores the low address bits for halfword and word transfers, so misaligned buffers would silently be shifted.
+/// Such buffers can result from reinterpreting byte buffers, e.g. a `static mut [u8; N]` as `[u16; N / 2]`.
+pub(crate) fn debug_assert_aligned<W>(ptr: *const W) {
+    debug_assert_eq!(
+        ptr as usize % mem::align_of::<W>(),
+        0,
+        "buffer is not aligned for the DMA transfer width"
+    );
+}
+
+/// Copies `src` to `dst` using the DMA, freeing the CPU during large copies.
+///
+/// The transfer width is the [`Word`] size of the buffers.
+/// Channel 1 of DMA1 is used, as it is not occupied by any USART.
+/// The returned [`Future`] resolves to the channel and buffers after the copy has completed.
+/// Dropping it before completion cancels the copy.
+///
+/// # Panics
+///
+/// Panics if `src` and `dst` have different lengths.
+/// With debug assertions enabled, also panics if a buffer is not aligned for the transfer width.
+///
+/// # Examples
+///
+/// ```
+/// let (channel, src, dst) = dma::memcpy(channels.1, src, dst).await;
+/// ```
+pub fn memcpy<W, S, D>(mut channel: C1, src: S, mut dst: D) -> Memcpy<S, D>
+where
+    W: Word,
+    S: StaticReadBuffer<Word = W>,
+    D: StaticWriteBuffer<Word = W>,
+{
+    // Safety: The buffers are owned by the future until the transfer has been stopped
+    let (src_ptr, src_len) = unsafe { src.static_read_buffer() };
+    let (dst_ptr, dst_len) = unsafe { dst.static_write_buffer() };
+    assert_eq!(src_len, dst_len, "buffers have different lengths");
+    debug_assert_aligned(src_ptr);
+    debug_assert_aligned(dst_ptr);
+
+    // With DIR set, the channel reads from the memory address and writes to the peripheral address
+    channel.set_memory_address(src_ptr as u32, true);
+    channel.set_peripheral_address(dst_ptr as u32, true);
+    channel.set_transfer_length(src_len);
+    channel.ch().cr.modify(|_, w| {
+        W::configure(w)
+            .mem2mem()
+            .set_bit()
+            .pl()
+            .medium()
+            .circ()
+            .clear_bit()
+            .dir()
+            .set_bit()
+    });
+    channel.listen(Event::TransferComplete);
+    channel.start();
+
+    Memcpy(Some((channel, src, dst)))
+}
+
+/// [`Future`] returned by [`memcpy`].
+///
+/// Polling it after completion returns [`Poll::Pending`] instead of panicking.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Memcpy<S, D>(Option<(C1, S, D)>);
+
+impl<S, D> Future for Memcpy<S, D>
+where
+    S: Unpin,
+    D: Unpin,
+{
+    type Output = (C1, S, D);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.0.is_none() {
+            // Polled after completion, see FusedFuture
+            return Poll::Pending;
+        }
+        // Safety: This is an atomic read of the interrupt status register
+        let done = unsafe { (*DMA1::ptr()).isr.read().tcif1().bit_is_set() };
+        if done {
+            let (mut channel, src, dst) = self.0.take().unwrap();
+            channel.stop();
+            Poll::Ready((channel, src, dst))
+        } else {
+            waker_interrupt!(DMA1_CHANNEL1, cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl<S, D> FusedFuture for Memcpy<S, D>
+where
+    S: Unpin,
+    D: Unpin,
+{
+    fn is_terminated(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+impl<S, D> Drop for Memcpy<S, D> {
+    fn drop(&mut self) {
+        if let Some((channel, _, _)) = &mut self.0 {
+            channel.stop();
+        }
+    }
+}
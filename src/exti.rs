@@ -0,0 +1,565 @@
+//! [`Future`]-based abstractions for external interrupts (EXTI).
+//!
+//! An [`AsyncPin`] awaits edges on a GPIO pin via the EXTI line of the same number.
+//! An [`AsyncPvd`] awaits the supply voltage falling below a threshold via EXTI line 16, which is connected to the programmable voltage detector (PVD).
+//! This allows reacting to a brown-out warning, e.g. by saving state before the power is lost.
+//!
+//! The EXTI interrupts are only declared with the `exti` feature, as they are commonly used otherwise.
+//! Lines 5 to 9 and lines 10 to 15 each share an interrupt, so only one future per group of lines can wait at a time.
+//!
+//! # Examples
+//!
+//! ```
+//! let button = gpioa.pa0.into_pull_up_input(&mut gpioa.crl);
+//! let mut button = AsyncPin::new(button, &mut afio, &dp.EXTI, Edge::FALLING);
+//! loop {
+//!     button.trigger().await;
+//!     led.toggle().unwrap();
+//! }
+//! ```
+//!
+//! [`Future`]: core::future::Future
+
+use crate::{
+    nvic,
+    timer::{self, AsyncTimer, Delay, Instance, Ticks},
+};
+use core::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use cortex_m::interrupt;
+use embedded_hal::digital::v2::{InputPin, PinState};
+use futures::{future, ready, stream::Stream};
+use stm32f1xx_hal::{
+    afio,
+    gpio::{gpioa, gpiob, gpioc, gpiod, gpioe, Edge, ExtiPin},
+    pac::{Interrupt, AFIO, EXTI, PWR, RCC},
+    rcc::APB1,
+};
+
+mod sealed {
+    pub trait ExtiLine {
+        /// The number of the pin, which is also the number of its EXTI line.
+        const LINE: u8;
+
+        /// The source selection of the pin's port in the AFIO external interrupt configuration registers.
+        const PORT: u32;
+    }
+}
+
+/// A GPIO pin which can be the source of an EXTI line, i.e., any pin of GPIOA to GPIOE.
+pub trait ExtiLine: sealed::ExtiLine {}
+
+macro_rules! exti_line {
+    ($($gpiox:ident: $port:literal, [$($PXi:ident: $i:literal),+],)+) => {
+        $(
+            $(
+                impl<MODE> sealed::ExtiLine for $gpiox::$PXi<MODE> {
+                    const LINE: u8 = $i;
+                    const PORT: u32 = $port;
+                }
+
+                impl<MODE> ExtiLine for $gpiox::$PXi<MODE> {}
+            )+
+        )+
+    };
+}
+
+exti_line!(
+    gpioa: 0, [PA0: 0, PA1: 1, PA2: 2, PA3: 3, PA4: 4, PA5: 5, PA6: 6, PA7: 7, PA8: 8, PA9: 9, PA10: 10, PA11: 11, PA12: 12, PA13: 13, PA14: 14, PA15: 15],
+    gpiob: 1, [PB0: 0, PB1: 1, PB2: 2, PB3: 3, PB4: 4, PB5: 5, PB6: 6, PB7: 7, PB8: 8, PB9: 9, PB10: 10, PB11: 11, PB12: 12, PB13: 13, PB14: 14, PB15: 15],
+    gpioc: 2, [PC0: 0, PC1: 1, PC2: 2, PC3: 3, PC4: 4, PC5: 5, PC6: 6, PC7: 7, PC8: 8, PC9: 9, PC10: 10, PC11: 11, PC12: 12, PC13: 13, PC14: 14, PC15: 15],
+    gpiod: 3, [PD0: 0, PD1: 1, PD2: 2, PD3: 3, PD4: 4, PD5: 5, PD6: 6, PD7: 7, PD8: 8, PD9: 9, PD10: 10, PD11: 11, PD12: 12, PD13: 13, PD14: 14, PD15: 15],
+    gpioe: 4, [PE0: 0, PE1: 1, PE2: 2, PE3: 3, PE4: 4, PE5: 5, PE6: 6, PE7: 7, PE8: 8, PE9: 9, PE10: 10, PE11: 11, PE12: 12, PE13: 13, PE14: 14, PE15: 15],
+);
+
+/// Registers `waker` to be woken by the interrupt of EXTI line `line`.
+fn arm(line: u8, waker: &Waker) {
+    match line {
+        0 => waker_interrupt!(EXTI0, waker),
+        1 => waker_interrupt!(EXTI1, waker),
+        2 => waker_interrupt!(EXTI2, waker),
+        3 => waker_interrupt!(EXTI3, waker),
+        4 => waker_interrupt!(EXTI4, waker),
+        5..=9 => waker_interrupt!(EXTI9_5, waker),
+        10..=15 => waker_interrupt!(EXTI15_10, waker),
+        _ => waker_interrupt!(PVD, waker),
+    }
+}
+
+/// Masks the interrupt of EXTI line `line`, e.g. after a future has stopped waiting for it.
+fn disarm(line: u8) {
+    let interrupt = match line {
+        0 => Interrupt::EXTI0,
+        1 => Interrupt::EXTI1,
+        2 => Interrupt::EXTI2,
+        3 => Interrupt::EXTI3,
+        4 => Interrupt::EXTI4,
+        5..=9 => Interrupt::EXTI9_5,
+        _ => Interrupt::EXTI15_10,
+    };
+    nvic::mask(interrupt);
+}
+
+/// An asynchronous abstraction over a GPIO pin, awaiting edges via its EXTI line.
+pub struct AsyncPin<P>(P);
+
+impl<P> AsyncPin<P>
+where
+    P: ExtiPin + ExtiLine,
+{
+    /// Makes `pin` the source of its EXTI line and enables the interrupt for `edge`.
+    pub fn new(mut pin: P, afio: &mut afio::Parts, exti: &EXTI, edge: Edge) -> Self {
+        pin.make_interrupt_source(afio);
+        pin.trigger_on_edge(exti, edge);
+        pin.enable_interrupt(exti);
+        Self(pin)
+    }
+
+    /// Wraps `pin`, which has already been made the source of its EXTI line with an enabled interrupt and trigger edge.
+    ///
+    /// Unlike [`new`](Self::new), this does not touch the EXTI and AFIO configuration, e.g. when it has been set up by HAL-based code.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the EXTI line is not sourced from the pin's port, its interrupt is not enabled or no trigger edge is enabled.
+    /// The AFIO clock needs to be enabled for checking the source.
+    pub fn from_configured(pin: P) -> Self {
+        let line = P::LINE;
+        // Safety: These are atomic reads of the configuration registers
+        let (afio, exti) = unsafe { (&*AFIO::ptr(), &*EXTI::ptr()) };
+        let exticr = match line / 4 {
+            0 => afio.exticr1.read().bits(),
+            1 => afio.exticr2.read().bits(),
+            2 => afio.exticr3.read().bits(),
+            _ => afio.exticr4.read().bits(),
+        };
+        let port = exticr >> (4 * (line % 4)) & 0xf;
+        assert!(port == P::PORT, "EXTI line is not sourced from the pin");
+        assert!(
+            exti.imr.read().bits() & 1 << line != 0,
+            "EXTI interrupt is not enabled"
+        );
+        let edges = exti.rtsr.read().bits() | exti.ftsr.read().bits();
+        assert!(edges & 1 << line != 0, "no EXTI trigger edge is enabled");
+        Self(pin)
+    }
+
+    /// Disables the interrupt of the EXTI line and releases the pin.
+    ///
+    /// The pin is left as the source of the EXTI line.
+    pub fn release(mut self, exti: &EXTI) -> P {
+        self.0.disable_interrupt(exti);
+        self.0
+    }
+
+    /// Returns whether an edge has been detected which has not been awaited yet, without clearing the pending bit.
+    ///
+    /// This allows deciding whether to await [`trigger`](Self::trigger), e.g. in a synchronous initialization path.
+    pub fn is_triggered(&mut self) -> bool {
+        self.0.check_interrupt()
+    }
+
+    /// Raises the EXTI line by software, as if an edge had been detected.
+    ///
+    /// This sets the pending bit like a real edge, so a pending [`trigger`](Self::trigger) resolves and [`is_triggered`](Self::is_triggered) returns `true`.
+    /// This is useful for exercising edge handlers in self-tests without an external signal.
+    /// Like real edges, software triggers are merged until the pending bit has been cleared.
+    pub fn trigger_software(&mut self) {
+        // Safety: Only the software interrupt bit of our line is set
+        let exti = unsafe { &*EXTI::ptr() };
+        interrupt::free(|_| {
+            exti.swier
+                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << P::LINE) })
+        });
+    }
+
+    /// Creates a [`Future`] that resolves once an edge has been detected, clearing the pending bit of the EXTI line.
+    ///
+    /// An edge detected before this has been called, which has not been awaited yet, resolves this immediately.
+    pub fn trigger(&mut self) -> impl Future<Output = ()> + '_ {
+        future::poll_fn(move |cx| self.poll_trigger(cx))
+    }
+
+    /// Clears the pending bit of the EXTI line, discarding a detected edge.
+    fn discard(&mut self) {
+        self.0.clear_interrupt_pending_bit();
+    }
+
+    /// Polls for an edge, see [`trigger`](Self::trigger).
+    fn poll_trigger(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0.check_interrupt() {
+            self.0.clear_interrupt_pending_bit();
+            Poll::Ready(())
+        } else {
+            arm(P::LINE, cx.waker());
+            Poll::Pending
+        }
+    }
+
+    /// Resolves once `n` edges have been detected.
+    ///
+    /// The pending bit of the EXTI line only latches one edge until the task clears it.
+    /// Edges arriving faster than the task is woken and polled are thus merged and counted once, which delays the completion.
+    /// For counting bursts faster than the wakeup latency, clock a timer from the pin instead.
+    pub async fn wait_for_edges(&mut self, n: u32) {
+        for _ in 0..n {
+            self.trigger().await;
+        }
+    }
+}
+
+/// The pin which has won [`race_pins`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Winner {
+    /// The first pin has detected an edge first.
+    A,
+    /// The second pin has detected an edge first.
+    B,
+}
+
+/// Creates a [`Future`] that resolves to the pin which detects an edge first, e.g. for an OK and a Cancel button.
+///
+/// If both pins have detected an edge by the time the future is polled, `a` wins.
+/// Afterwards, the interrupt of the losing pin is masked again and an edge it has detected meanwhile is discarded.
+/// Thus, it does not wake the task or resolve its next [`trigger`](AsyncPin::trigger) spuriously.
+///
+/// # Examples
+///
+/// ```
+/// match exti::race_pins(&mut ok, &mut cancel).await {
+///     Winner::A => confirm(),
+///     Winner::B => abort(),
+/// }
+/// ```
+pub fn race_pins<'a, A, B>(
+    a: &'a mut AsyncPin<A>,
+    b: &'a mut AsyncPin<B>,
+) -> impl Future<Output = Winner> + 'a
+where
+    A: ExtiPin + ExtiLine,
+    B: ExtiPin + ExtiLine,
+{
+    future::poll_fn(move |cx| {
+        let winner = if a.poll_trigger(cx).is_ready() {
+            Winner::A
+        } else if b.poll_trigger(cx).is_ready() {
+            Winner::B
+        } else {
+            return Poll::Pending;
+        };
+
+        match winner {
+            Winner::A => {
+                disarm(B::LINE);
+                b.discard();
+            }
+            Winner::B => {
+                disarm(A::LINE);
+                a.discard();
+            }
+        }
+        Poll::Ready(winner)
+    })
+}
+
+/// An event of a button, see [`ButtonEvents`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonEvent {
+    /// The button has been pressed.
+    Press,
+    /// The button has been released.
+    Release,
+    /// The button has been held for the long press time.
+    LongPress,
+}
+
+/// The configuration of [`ButtonEvents`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ButtonConfig {
+    /// The time the pin needs to settle after an edge before its level is read.
+    pub debounce: Ticks,
+    /// The time after the debounced press at which [`ButtonEvent::LongPress`] is yielded.
+    pub long_press: Ticks,
+    /// The level of the pin while the button is pressed, e.g. [`PinState::Low`] for a button pulling an input with pull-up to ground.
+    pub pressed: PinState,
+}
+
+/// A [`Stream`] of debounced [`ButtonEvent`]s of a button on an [`AsyncPin`].
+///
+/// The pin needs to trigger on both edges, i.e., [`Edge::RISING_FALLING`].
+/// After each edge, the level of the pin is read once it has settled for the debounce time.
+/// A press is followed by a long press if the button is held for the long press time and by a release in any case.
+/// Bounces while the button is held restart the long press time.
+/// The stream never finishes.
+///
+/// # Examples
+///
+/// ```
+/// let config = ButtonConfig {
+///     debounce: Ticks(720_000),
+///     long_press: Ticks(72_000_000),
+///     pressed: PinState::Low,
+/// };
+/// let mut events = ButtonEvents::new(&mut button, &mut timer, config);
+/// while let Some(event) = events.next().await {
+///     match event {
+///         ButtonEvent::Press => led.set_high().unwrap(),
+///         ButtonEvent::Release => led.set_low().unwrap(),
+///         ButtonEvent::LongPress => reset_settings(),
+///     }
+/// }
+/// ```
+#[must_use = "streams do nothing unless polled"]
+pub struct ButtonEvents<'a, P, T: Instance> {
+    pin: &'a mut AsyncPin<P>,
+    delay: Delay<'a, T>,
+    config: ButtonConfig,
+    /// The debounced state of the button.
+    pressed: bool,
+    long_pressed: bool,
+    /// Whether the delay is waiting for the pin to settle, instead of for a long press.
+    debouncing: bool,
+}
+
+impl<'a, P, T> ButtonEvents<'a, P, T>
+where
+    P: ExtiPin + ExtiLine + InputPin<Error = Infallible>,
+    T: Instance,
+{
+    /// Creates a new [`ButtonEvents`], which reads the initial state of the button after the debounce time.
+    ///
+    /// A button pressed initially thus yields [`ButtonEvent::Press`] first.
+    pub fn new(
+        pin: &'a mut AsyncPin<P>,
+        timer: &'a mut AsyncTimer<T>,
+        config: ButtonConfig,
+    ) -> Self {
+        let delay = timer.delay(config.debounce);
+        Self {
+            pin,
+            delay,
+            config,
+            pressed: false,
+            long_pressed: false,
+            debouncing: true,
+        }
+    }
+
+    fn is_pressed(&self) -> bool {
+        // Unwrapping: Reading the pin is infallible
+        let high = self.pin.0.is_high().unwrap();
+        high == (self.config.pressed == PinState::High)
+    }
+}
+
+impl<P, T> Stream for ButtonEvents<'_, P, T>
+where
+    P: ExtiPin + ExtiLine + InputPin<Error = Infallible>,
+    T: Instance,
+{
+    type Item = ButtonEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.debouncing {
+                timer::unwrap_infallible(ready!(Pin::new(&mut this.delay).poll(cx)));
+                this.debouncing = false;
+                // Edges while settling are superseded by the level, while an edge after clearing starts debouncing again
+                if this.pin.0.check_interrupt() {
+                    this.pin.0.clear_interrupt_pending_bit();
+                }
+                let pressed = this.is_pressed();
+                let changed = pressed != this.pressed;
+                this.pressed = pressed;
+                if pressed && changed {
+                    this.long_pressed = false;
+                }
+                if pressed && !this.long_pressed {
+                    this.delay.restart(this.config.long_press);
+                } else {
+                    this.delay.stop();
+                }
+                if changed {
+                    let event = if pressed {
+                        ButtonEvent::Press
+                    } else {
+                        ButtonEvent::Release
+                    };
+                    return Poll::Ready(Some(event));
+                }
+            }
+
+            if this.pin.poll_trigger(cx).is_ready() {
+                this.delay.restart(this.config.debounce);
+                this.debouncing = true;
+                continue;
+            }
+
+            if this.pressed && !this.long_pressed {
+                timer::unwrap_infallible(ready!(Pin::new(&mut this.delay).poll(cx)));
+                this.delay.stop();
+                this.long_pressed = true;
+                return Poll::Ready(Some(ButtonEvent::LongPress));
+            }
+            return Poll::Pending;
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+/// The EXTI line connected to the PVD output.
+const LINE_PVD: u8 = 16;
+
+/// The PVD enable bit of the power control register.
+const PWR_CR_PVDE: u32 = 1 << 4;
+
+/// The offset of the PVD level selection bits in the power control register.
+const PWR_CR_PLS_SHIFT: u32 = 5;
+
+/// The threshold of the [`AsyncPvd`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PvdLevel {
+    /// 2.2 V
+    V2_2,
+    /// 2.3 V
+    V2_3,
+    /// 2.4 V
+    V2_4,
+    /// 2.5 V
+    V2_5,
+    /// 2.6 V
+    V2_6,
+    /// 2.7 V
+    V2_7,
+    /// 2.8 V
+    V2_8,
+    /// 2.9 V
+    V2_9,
+}
+
+/// An asynchronous abstraction over the programmable voltage detector.
+///
+/// The thresholds have a hysteresis of about 100 mV, so the falling threshold is slightly below the configured level.
+///
+/// # Examples
+///
+/// ```
+/// let mut pvd = AsyncPvd::new(dp.PWR, &dp.EXTI, &mut rcc.apb1, PvdLevel::V2_9);
+/// pvd.below_threshold().await;
+/// save_state();
+/// ```
+pub struct AsyncPvd(PWR);
+
+impl AsyncPvd {
+    /// Enables the PVD with the given threshold and the rising edge of EXTI line 16.
+    pub fn new(pwr: PWR, exti: &EXTI, _apb1: &mut APB1, level: PvdLevel) -> Self {
+        // Safety: We hold the APB1 registers
+        unsafe { (*RCC::ptr()).apb1enr.modify(|_, w| w.pwren().set_bit()) };
+
+        let pls = (level as u32) << PWR_CR_PLS_SHIFT;
+        // Safety: Any level is valid
+        pwr.cr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b111 << PWR_CR_PLS_SHIFT) | pls | PWR_CR_PVDE)
+        });
+
+        // The PVD output rises when VDD falls below the threshold
+        interrupt::free(|_| {
+            // Safety: Only the bits of the PVD line are changed
+            exti.rtsr
+                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << LINE_PVD) });
+            exti.imr
+                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << LINE_PVD) });
+        });
+        Self(pwr)
+    }
+
+    /// Returns whether VDD is currently below the threshold.
+    pub fn is_below_threshold(&self) -> bool {
+        self.0.csr.read().pvdo().bit_is_set()
+    }
+
+    /// Creates a [`Future`] that resolves once VDD is below the threshold.
+    ///
+    /// If VDD is already below the threshold, this resolves immediately.
+    pub fn below_threshold(&mut self) -> impl Future<Output = ()> + '_ {
+        future::poll_fn(move |cx| {
+            // Safety: Writing 1 only clears the pending bit of the PVD line
+            let exti = unsafe { &*EXTI::ptr() };
+            exti.pr.write(|w| unsafe { w.bits(1 << LINE_PVD) });
+            if self.is_below_threshold() {
+                Poll::Ready(())
+            } else {
+                arm(LINE_PVD, cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Disables the PVD and EXTI line 16, and releases the PWR peripheral.
+    pub fn release(self, exti: &EXTI) -> PWR {
+        interrupt::free(|_| {
+            // Safety: Only the bits of the PVD line are changed
+            exti.imr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << LINE_PVD)) });
+            exti.rtsr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << LINE_PVD)) });
+        });
+        self.0.cr.modify(|_, w| w.pvde().clear_bit());
+        self.0
+    }
+}
+
+/// A set of EXTI lines, iterating over their numbers in ascending order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lines(u32);
+
+impl Lines {
+    /// Returns whether `line` is in the set.
+    pub fn contains(&self, line: u8) -> bool {
+        self.0 & 1 << line != 0
+    }
+
+    /// Returns the set as a bit mask, where bit `n` is set for line `n`.
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Iterator for Lines {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0 == 0 {
+            return None;
+        }
+        let line = self.0.trailing_zeros() as u8;
+        self.0 &= self.0 - 1;
+        Some(line)
+    }
+}
+
+/// Returns the EXTI lines with a pending edge, e.g. the lines which have woken the core from STOP mode.
+///
+/// The pending bits are latched until the edges have been awaited, as the interrupts only wake the tasks.
+/// Thus, this should be called on resuming, before the tasks awaiting the lines are polled.
+///
+/// # Examples
+///
+/// ```
+/// asm::wfi(); // In STOP mode
+/// for line in exti::last_wakeup_lines() {
+///     defmt::info!("woken by line {:u8}", line);
+/// }
+/// ```
+pub fn last_wakeup_lines() -> Lines {
+    // Safety: This is an atomic read of the pending register
+    Lines(unsafe { (*EXTI::ptr()).pr.read().bits() })
+}
@@ -0,0 +1,645 @@
+//! [`Stream`] adapters for framing byte streams, such as [`RxStream`](crate::serial::RxStream).
+//!
+//! The adapters work on streams of byte buffers and stitch frames and values across buffer boundaries.
+
+use as_slice::AsSlice;
+use core::{
+    convert::TryInto,
+    marker::PhantomData,
+    mem,
+    pin::Pin,
+    str,
+    task::{Context, Poll},
+};
+use futures::{ready, stream::Stream};
+use heapless::{String, Vec};
+
+/// An error of a framing [`Stream`] adapter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error<E> {
+    /// The underlying stream returned an error.
+    Stream(E),
+    /// A frame did not fit into the accumulation buffer and has been discarded.
+    TooLong,
+    /// A line did not fit into the accumulation buffer and has been discarded.
+    LineTooLong,
+    /// A line was not valid UTF-8 and has been discarded.
+    NonUtf8,
+}
+
+/// The byte order of multi-byte values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    /// The least significant byte comes first.
+    Little,
+    /// The most significant byte comes first.
+    Big,
+}
+
+mod sealed {
+    pub trait Sample: Sized {
+        /// The size of the value in bytes.
+        const SIZE: usize;
+
+        /// Decodes the value from `SIZE` little-endian bytes.
+        fn from_le(bytes: &[u8]) -> Self;
+
+        /// Decodes the value from `SIZE` big-endian bytes.
+        fn from_be(bytes: &[u8]) -> Self;
+    }
+}
+
+macro_rules! sample {
+    ($($T:ty),+) => {
+        $(
+            impl sealed::Sample for $T {
+                const SIZE: usize = mem::size_of::<$T>();
+
+                fn from_le(bytes: &[u8]) -> Self {
+                    <$T>::from_le_bytes(bytes.try_into().unwrap())
+                }
+
+                fn from_be(bytes: &[u8]) -> Self {
+                    <$T>::from_be_bytes(bytes.try_into().unwrap())
+                }
+            }
+        )+
+    }
+}
+
+sample!(u16, i16, u32, i32);
+
+/// An extension trait for framing streams of byte buffers.
+pub trait FramingExt<BUF, E>: Stream<Item = Result<BUF, E>> + Sized {
+    /// Yields a frame each time `delimiter` is received.
+    ///
+    /// Frames are accumulated in a buffer of `N` bytes.
+    /// Frames not fitting into this buffer are discarded and reported as [`Error::TooLong`].
+    /// The delimiter is excluded from the frames, unless configured otherwise with [`Delimited::include_delimiter`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut lines = rx_stream.delimited::<64>(b'\n');
+    /// while let Some(line) = lines.next().await {
+    ///     handle(line?);
+    /// }
+    /// ```
+    fn delimited<const N: usize>(self, delimiter: u8) -> Delimited<Self, BUF, N> {
+        Delimited {
+            stream: self,
+            delimiter,
+            include_delimiter: false,
+            frame: Vec::new(),
+            too_long: false,
+            buf: None,
+        }
+    }
+
+    /// Yields the received bytes in records of `N` bytes, regardless of the buffer boundaries.
+    ///
+    /// When the underlying stream finishes, a trailing partial record is available from [`Chunks::remainder`].
+    /// Alternatively, it can be padded and yielded as a full record, configured with [`Chunks::pad_remainder`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut records = rx_stream.chunks::<16>();
+    /// while let Some(record) = records.next().await {
+    ///     handle(record?);
+    /// }
+    /// ```
+    fn chunks<const N: usize>(self) -> Chunks<Self, BUF, N> {
+        Chunks {
+            stream: self,
+            pad: None,
+            chunk: [0; N],
+            len: 0,
+            buf: None,
+            terminated: false,
+        }
+    }
+
+    /// Yields the frames of `N` bytes following each occurrence of `sync`, e.g. `&[0xAA, 0x55]`.
+    ///
+    /// Bytes before a sync word are discarded, also across buffer boundaries.
+    /// After each frame, the stream is scanned for the next sync word, so it resynchronizes after a glitch.
+    /// The sync word is excluded from the frames.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sync` is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut frames = rx_stream.synced::<16>(&[0xAA, 0x55]);
+    /// while let Some(frame) = frames.next().await {
+    ///     handle(frame?);
+    /// }
+    /// ```
+    fn synced<const N: usize>(self, sync: &[u8]) -> Synced<'_, Self, BUF, N> {
+        assert!(!sync.is_empty(), "sync word is empty");
+        Synced {
+            stream: self,
+            sync,
+            matched: 0,
+            frame: [0; N],
+            len: 0,
+            buf: None,
+        }
+    }
+
+    /// Yields each line terminated by LF or CR LF as a string of up to `N` bytes.
+    ///
+    /// The line terminator is excluded from the lines, so a line of `N` bytes fits regardless of its terminator.
+    /// Lines not fitting into `N` bytes are discarded and reported as [`Error::LineTooLong`], resynchronizing at the next line.
+    /// Lines which are not valid UTF-8 are discarded and reported as [`Error::NonUtf8`].
+    /// This is useful for parsing line-based protocols, such as AT command responses and NMEA sentences.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut lines = rx_stream.lines::<82>();
+    /// while let Some(line) = lines.next().await {
+    ///     if line? == "OK" {
+    ///         break;
+    ///     }
+    /// }
+    /// ```
+    fn lines<const N: usize>(self) -> Lines<Self, BUF, N> {
+        Lines {
+            stream: self,
+            line: Vec::new(),
+            cr: false,
+            too_long: false,
+            buf: None,
+        }
+    }
+
+    /// Logs each received buffer as a hex dump via `defmt` at the debug level, passing it through unchanged.
+    ///
+    /// Each line of the dump shows the offset and up to 16 bytes.
+    /// This is useful for debugging frames failing to parse, as the adapter can be inserted and removed without changing the downstream code.
+    #[cfg(feature = "defmt")]
+    fn inspect_defmt(self) -> InspectDefmt<Self> {
+        InspectDefmt(self)
+    }
+
+    /// Yields the multi-byte values of type `T` encoded in the received bytes with the given byte order.
+    ///
+    /// Values straddling buffer boundaries are reassembled.
+    /// `T` is one of [`u16`], [`i16`], [`u32`] and [`i32`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut samples = rx_stream.decode::<u16>(Endianness::Little);
+    /// while let Some(sample) = samples.next().await {
+    ///     handle(sample?);
+    /// }
+    /// ```
+    fn decode<T>(self, endianness: Endianness) -> Decode<Self, BUF, T> {
+        Decode {
+            stream: self,
+            endianness,
+            partial: [0; 4],
+            len: 0,
+            buf: None,
+            _sample: PhantomData,
+        }
+    }
+}
+
+impl<S, BUF, E> FramingExt<BUF, E> for S
+where
+    S: Stream<Item = Result<BUF, E>>,
+    BUF: AsSlice<Element = u8>,
+{
+}
+
+/// Stream for the [`delimited`](FramingExt::delimited) method.
+#[must_use = "streams do nothing unless polled"]
+pub struct Delimited<S, BUF, const N: usize> {
+    stream: S,
+    delimiter: u8,
+    include_delimiter: bool,
+    frame: Vec<u8, N>,
+    too_long: bool,
+    /// The buffer being scanned and the position to continue at.
+    buf: Option<(BUF, usize)>,
+}
+
+impl<S, BUF, const N: usize> Delimited<S, BUF, N> {
+    /// Configures whether the delimiter is included at the end of each frame.
+    pub fn include_delimiter(mut self, include_delimiter: bool) -> Self {
+        self.include_delimiter = include_delimiter;
+        self
+    }
+
+    /// Releases the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        if self.frame.extend_from_slice(bytes).is_err() {
+            self.frame.clear();
+            self.too_long = true;
+        }
+    }
+}
+
+impl<S, BUF, E, const N: usize> Stream for Delimited<S, BUF, N>
+where
+    S: Stream<Item = Result<BUF, E>> + Unpin,
+    BUF: AsSlice<Element = u8> + Unpin,
+{
+    type Item = Result<Vec<u8, N>, Error<E>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((buf, pos)) = this.buf.take() {
+                let bytes = &buf.as_slice()[pos..];
+                match bytes.iter().position(|&byte| byte == this.delimiter) {
+                    Some(i) => {
+                        let end = if this.include_delimiter { i + 1 } else { i };
+                        this.extend(&bytes[..end]);
+                        this.buf = Some((buf, pos + i + 1));
+
+                        let frame = mem::take(&mut this.frame);
+                        let res = if mem::replace(&mut this.too_long, false) {
+                            Err(Error::TooLong)
+                        } else {
+                            Ok(frame)
+                        };
+                        return Poll::Ready(Some(res));
+                    }
+                    None => this.extend(bytes),
+                }
+            }
+
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(buf)) => this.buf = Some((buf, 0)),
+                Some(Err(err)) => return Poll::Ready(Some(Err(Error::Stream(err)))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// Stream for the [`chunks`](FramingExt::chunks) method.
+#[must_use = "streams do nothing unless polled"]
+pub struct Chunks<S, BUF, const N: usize> {
+    stream: S,
+    pad: Option<u8>,
+    chunk: [u8; N],
+    len: usize,
+    /// The buffer being split and the position to continue at.
+    buf: Option<(BUF, usize)>,
+    terminated: bool,
+}
+
+impl<S, BUF, const N: usize> Chunks<S, BUF, N> {
+    /// Configures padding a trailing partial record with `fill` and yielding it, when the underlying stream finishes.
+    pub fn pad_remainder(mut self, fill: u8) -> Self {
+        self.pad = Some(fill);
+        self
+    }
+
+    /// Returns the bytes of the current partial record.
+    ///
+    /// After the stream has finished without padding, this is the trailing partial record.
+    pub fn remainder(&self) -> &[u8] {
+        &self.chunk[..self.len]
+    }
+
+    /// Releases the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, BUF, E, const N: usize> Stream for Chunks<S, BUF, N>
+where
+    S: Stream<Item = Result<BUF, E>> + Unpin,
+    BUF: AsSlice<Element = u8> + Unpin,
+{
+    type Item = Result<[u8; N], E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((buf, pos)) = this.buf.take() {
+                let bytes = &buf.as_slice()[pos..];
+                let n = bytes.len().min(N - this.len);
+                this.chunk[this.len..this.len + n].copy_from_slice(&bytes[..n]);
+                this.len += n;
+                this.buf = Some((buf, pos + n));
+
+                if this.len == N {
+                    this.len = 0;
+                    return Poll::Ready(Some(Ok(this.chunk)));
+                }
+            }
+
+            if this.terminated {
+                return Poll::Ready(None);
+            }
+
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(buf)) => this.buf = Some((buf, 0)),
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    this.terminated = true;
+                    match this.pad {
+                        Some(fill) if this.len > 0 => {
+                            this.chunk[this.len..].fill(fill);
+                            this.len = 0;
+                            return Poll::Ready(Some(Ok(this.chunk)));
+                        }
+                        _ => return Poll::Ready(None),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Stream for the [`synced`](FramingExt::synced) method.
+#[must_use = "streams do nothing unless polled"]
+pub struct Synced<'a, S, BUF, const N: usize> {
+    stream: S,
+    sync: &'a [u8],
+    /// The number of bytes of the sync word matched so far.
+    matched: usize,
+    frame: [u8; N],
+    len: usize,
+    /// The buffer being scanned and the position to continue at.
+    buf: Option<(BUF, usize)>,
+}
+
+impl<S, BUF, const N: usize> Synced<'_, S, BUF, N> {
+    /// Releases the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Advances the sync word matching by `byte`.
+    fn match_sync(&mut self, byte: u8) {
+        if byte == self.sync[self.matched] {
+            self.matched += 1;
+            return;
+        }
+
+        // Fall back to the longest matched suffix which is also a prefix of the sync word
+        let matched = &self.sync[..self.matched];
+        self.matched = (1..=self.matched)
+            .rev()
+            .find(|&len| {
+                let (rest, last) = (&matched[self.matched + 1 - len..], self.sync[len - 1]);
+                last == byte && rest == &self.sync[..len - 1]
+            })
+            .unwrap_or(0);
+    }
+}
+
+impl<S, BUF, E, const N: usize> Stream for Synced<'_, S, BUF, N>
+where
+    S: Stream<Item = Result<BUF, E>> + Unpin,
+    BUF: AsSlice<Element = u8> + Unpin,
+{
+    type Item = Result<[u8; N], E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((buf, mut pos)) = this.buf.take() {
+                let bytes = buf.as_slice();
+                while pos < bytes.len() {
+                    if this.matched < this.sync.len() {
+                        this.match_sync(bytes[pos]);
+                        pos += 1;
+                        continue;
+                    }
+
+                    let n = (bytes.len() - pos).min(N - this.len);
+                    this.frame[this.len..this.len + n].copy_from_slice(&bytes[pos..pos + n]);
+                    this.len += n;
+                    pos += n;
+                    if this.len == N {
+                        this.len = 0;
+                        this.matched = 0;
+                        this.buf = Some((buf, pos));
+                        return Poll::Ready(Some(Ok(this.frame)));
+                    }
+                }
+            }
+
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(buf)) => this.buf = Some((buf, 0)),
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// Stream for the [`lines`](FramingExt::lines) method.
+#[must_use = "streams do nothing unless polled"]
+pub struct Lines<S, BUF, const N: usize> {
+    stream: S,
+    line: Vec<u8, N>,
+    /// Whether the last received byte has been a CR, which is only added to the line if no LF follows.
+    cr: bool,
+    too_long: bool,
+    /// The buffer being scanned and the position to continue at.
+    buf: Option<(BUF, usize)>,
+}
+
+impl<S, BUF, const N: usize> Lines<S, BUF, N> {
+    /// Releases the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        if mem::take(&mut self.cr) {
+            self.push(b"\r");
+        }
+        let bytes = match bytes.strip_suffix(b"\r") {
+            Some(bytes) => {
+                self.cr = true;
+                bytes
+            }
+            None => bytes,
+        };
+        self.push(bytes);
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        if self.line.extend_from_slice(bytes).is_err() {
+            self.line.clear();
+            self.too_long = true;
+        }
+    }
+
+    /// Ends the current line at an LF, discarding a preceding CR.
+    fn take_line<E>(&mut self) -> Result<String<N>, Error<E>> {
+        self.cr = false;
+        let line = mem::take(&mut self.line);
+        if mem::take(&mut self.too_long) {
+            return Err(Error::LineTooLong);
+        }
+
+        str::from_utf8(&line)
+            .map(|line| {
+                let mut string = String::new();
+                // The line fits, as it has been accumulated in a buffer of the same capacity
+                string.push_str(line).unwrap();
+                string
+            })
+            .map_err(|_| Error::NonUtf8)
+    }
+}
+
+impl<S, BUF, E, const N: usize> Stream for Lines<S, BUF, N>
+where
+    S: Stream<Item = Result<BUF, E>> + Unpin,
+    BUF: AsSlice<Element = u8> + Unpin,
+{
+    type Item = Result<String<N>, Error<E>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((buf, pos)) = this.buf.take() {
+                let bytes = &buf.as_slice()[pos..];
+                match bytes.iter().position(|&byte| byte == b'\n') {
+                    Some(i) => {
+                        this.extend(&bytes[..i]);
+                        this.buf = Some((buf, pos + i + 1));
+                        return Poll::Ready(Some(this.take_line()));
+                    }
+                    None => this.extend(bytes),
+                }
+            }
+
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(buf)) => this.buf = Some((buf, 0)),
+                Some(Err(err)) => return Poll::Ready(Some(Err(Error::Stream(err)))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// Stream for the [`inspect_defmt`](FramingExt::inspect_defmt) method.
+#[cfg(feature = "defmt")]
+#[must_use = "streams do nothing unless polled"]
+pub struct InspectDefmt<S>(S);
+
+#[cfg(feature = "defmt")]
+impl<S> InspectDefmt<S> {
+    /// Releases the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.0
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<S, BUF, E> Stream for InspectDefmt<S>
+where
+    S: Stream<Item = Result<BUF, E>> + Unpin,
+    BUF: AsSlice<Element = u8>,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+
+        let item = ready!(Pin::new(&mut self.get_mut().0).poll_next(cx));
+        if let Some(Ok(buf)) = &item {
+            let bytes = buf.as_slice();
+            defmt::debug!("received {:usize} bytes", bytes.len());
+            for (i, chunk) in bytes.chunks(16).enumerate() {
+                // Two hex digits and a space per byte
+                let mut line = [b' '; 48];
+                for (byte, hex) in chunk.iter().zip(line.chunks_mut(3)) {
+                    hex[0] = HEX[usize::from(byte >> 4)];
+                    hex[1] = HEX[usize::from(byte & 0xf)];
+                }
+                let line = str::from_utf8(&line[..3 * chunk.len() - 1]).unwrap();
+                defmt::debug!("{:usize}: {:str}", 16 * i, line);
+            }
+        }
+        Poll::Ready(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+/// Stream for the [`decode`](FramingExt::decode) method.
+#[must_use = "streams do nothing unless polled"]
+pub struct Decode<S, BUF, T> {
+    stream: S,
+    endianness: Endianness,
+    /// The bytes of a value straddling buffer boundaries.
+    partial: [u8; 4],
+    len: usize,
+    /// The buffer being decoded and the position to continue at.
+    buf: Option<(BUF, usize)>,
+    _sample: PhantomData<T>,
+}
+
+impl<S, BUF, T> Decode<S, BUF, T> {
+    /// Releases the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, BUF, E, T> Stream for Decode<S, BUF, T>
+where
+    S: Stream<Item = Result<BUF, E>> + Unpin,
+    BUF: AsSlice<Element = u8> + Unpin,
+    T: sealed::Sample + Unpin,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((buf, pos)) = this.buf.take() {
+                let bytes = &buf.as_slice()[pos..];
+                let missing = T::SIZE - this.len;
+                if bytes.len() >= missing {
+                    this.partial[this.len..T::SIZE].copy_from_slice(&bytes[..missing]);
+                    this.len = 0;
+                    this.buf = Some((buf, pos + missing));
+
+                    let bytes = &this.partial[..T::SIZE];
+                    let sample = match this.endianness {
+                        Endianness::Little => T::from_le(bytes),
+                        Endianness::Big => T::from_be(bytes),
+                    };
+                    return Poll::Ready(Some(Ok(sample)));
+                }
+                this.partial[this.len..this.len + bytes.len()].copy_from_slice(bytes);
+                this.len += bytes.len();
+            }
+
+            match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(buf)) => this.buf = Some((buf, 0)),
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Hooks for letting the core sleep while this crate's futures are pending.
+//!
+//! All futures of this crate are woken from interrupts.
+//! While they are pending, there is nothing to do for the core until one of these interrupts fires.
+//! Executors should therefore call [`sleep`] in their idle loop instead of spinning.
+//!
+//! # Examples
+//!
+//! ```
+//! loop {
+//!     if let Some(task) = ready_tasks.pop() {
+//!         task.poll();
+//!     } else {
+//!         idle::sleep();
+//!     }
+//! }
+//! ```
+//!
+//! If the executor can check for ready tasks with interrupts disabled, [`sleep_unless`] avoids relying on the event register altogether.
+//!
+//! The [`async-embedded`](https://github.com/rust-embedded-community/async-on-embedded) executor already sleeps this way.
+
+use cortex_m::{asm, interrupt, register::primask};
+
+/// Puts the core to sleep until an event occurs.
+///
+/// This uses WFE rather than WFI.
+/// Any interrupt of this crate firing after the executor has checked for ready tasks sets the event register, so the core does not sleep through the wakeup.
+/// Wakers which are invoked from another context should execute SEV for the same reason.
+pub fn sleep() {
+    asm::wfe();
+}
+
+/// Puts the core to sleep until an interrupt is pending, unless `is_ready` returns `true`.
+///
+/// `is_ready` is called with interrupts disabled and the core sleeps via WFI before enabling them again.
+/// An interrupt firing after `is_ready` has returned `false` stays pending, so WFI returns immediately instead of sleeping through the wakeup.
+/// The interrupt handler runs right after this function has enabled the interrupts again.
+/// Unlike [`sleep`], this does not require wakers invoked from another context to execute SEV, as long as they set the state checked by `is_ready` before pending an interrupt.
+///
+/// # Examples
+///
+/// ```
+/// loop {
+///     while let Some(task) = ready_tasks.pop() {
+///         task.poll();
+///     }
+///     idle::sleep_unless(|| !ready_tasks.is_empty());
+/// }
+/// ```
+pub fn sleep_unless<F>(is_ready: F)
+where
+    F: FnOnce() -> bool,
+{
+    let primask = primask::read();
+    interrupt::disable();
+    if !is_ready() {
+        asm::wfi();
+    }
+    if primask.is_active() {
+        // Safety: This is the end of the critical section started above
+        unsafe { interrupt::enable() }
+    }
+}
@@ -0,0 +1,142 @@
+//! A bridge from user interrupt handlers to [`Stream`]s.
+//!
+//! This allows integrating peripherals which are not supported by this crate into the same asynchronous model.
+//! An interrupt handler pushes items into an [`IsrSender`], while a task receives them from the corresponding [`IsrStream`].
+//!
+//! With the `user-interrupts` feature, the interrupts of this crate are declared by the application as well, which forwards them to [`on_interrupt`].
+//!
+//! # Examples
+//!
+//! ```
+//! static mut QUEUE: Queue<u8, 16> = Queue::new();
+//! static WAKER: AtomicWaker = AtomicWaker::new();
+//! static mut SENDER: Option<IsrSender<'static, u8, 16>> = None;
+//!
+//! #[interrupt]
+//! fn USB_LP_CAN_RX0() {
+//!     // Safety: SENDER is initialized before unmasking this interrupt and not accessed elsewhere
+//!     let sender = unsafe { SENDER.as_mut().unwrap() };
+//!     sender.send(read_endpoint()).ok();
+//! }
+//!
+//! // In main
+//! let (sender, mut stream) = isr::channel(unsafe { &mut QUEUE }, &WAKER);
+//! unsafe { SENDER = Some(sender) };
+//! while let Some(byte) = stream.next().await {
+//!     // ...
+//! }
+//! ```
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures::{
+    stream::{FusedStream, Stream},
+    task::AtomicWaker,
+};
+use heapless::spsc::{Consumer, Producer, Queue};
+#[cfg(feature = "user-interrupts")]
+use stm32f1xx_hal::pac::Interrupt;
+
+/// Handles `interrupt` for this crate, returning whether it is one of its interrupts.
+///
+/// This needs to be called from the application's handler of each interrupt of this crate that is in use, such as `TIM2` for an [`AsyncTimer`](crate::timer::AsyncTimer) of TIM2.
+/// After waking a task, the timer and DMA interrupts are masked until the task is polled, so they can't be shared with unrelated events.
+///
+/// # Examples
+///
+/// ```
+/// #[interrupt]
+/// fn TIM2() {
+///     isr::on_interrupt(Interrupt::TIM2);
+/// }
+/// ```
+#[cfg(feature = "user-interrupts")]
+pub fn on_interrupt(interrupt: Interrupt) -> bool {
+    let handled = crate::wakers::handle(interrupt);
+    #[cfg(feature = "usart-interrupts")]
+    let handled = handled || crate::serial::usart_wakers::handle(interrupt);
+    handled
+}
+
+/// Handles the SysTick exception for [`AsyncTimer`](crate::timer::AsyncTimer)s of [`SYST`](cortex_m::peripheral::SYST).
+///
+/// This needs to be called from the application's SysTick exception handler, e.g. when it also drives the time driver of an executor.
+///
+/// # Examples
+///
+/// ```
+/// #[exception]
+/// fn SysTick() {
+///     isr::on_systick();
+/// }
+/// ```
+#[cfg(all(feature = "systick", feature = "user-interrupts"))]
+pub fn on_systick() {
+    crate::timer::systick::handle();
+}
+
+/// Splits `queue` into an [`IsrSender`] for an interrupt handler and an [`IsrStream`] for a task.
+///
+/// `waker` is used for waking the task when an item has been sent.
+pub fn channel<'a, T, const N: usize>(
+    queue: &'a mut Queue<T, N>,
+    waker: &'a AtomicWaker,
+) -> (IsrSender<'a, T, N>, IsrStream<'a, T, N>) {
+    let (producer, consumer) = queue.split();
+    (IsrSender { producer, waker }, IsrStream { consumer, waker })
+}
+
+/// The sending half of an [`isr::channel`](channel), to be used from an interrupt handler.
+pub struct IsrSender<'a, T, const N: usize> {
+    producer: Producer<'a, T, N>,
+    waker: &'a AtomicWaker,
+}
+
+impl<T, const N: usize> IsrSender<'_, T, N> {
+    /// Sends `item` to the [`IsrStream`], waking the receiving task.
+    ///
+    /// Returns the item back if the queue is full.
+    pub fn send(&mut self, item: T) -> Result<(), T> {
+        self.producer.enqueue(item)?;
+        self.waker.wake();
+        Ok(())
+    }
+
+    /// Returns whether the queue is full.
+    pub fn is_full(&self) -> bool {
+        !self.producer.ready()
+    }
+}
+
+/// The receiving half of an [`isr::channel`](channel), yielding items sent by an interrupt handler.
+#[must_use = "streams do nothing unless polled"]
+pub struct IsrStream<'a, T, const N: usize> {
+    consumer: Consumer<'a, T, N>,
+    waker: &'a AtomicWaker,
+}
+
+impl<T, const N: usize> Stream for IsrStream<'_, T, N> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        // Register before checking, so an item sent in between is not missed
+        this.waker.register(cx.waker());
+        match this.consumer.dequeue() {
+            Some(item) => Poll::Ready(Some(item)),
+            None => Poll::Pending,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.consumer.len(), None)
+    }
+}
+
+impl<T, const N: usize> FusedStream for IsrStream<'_, T, N> {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
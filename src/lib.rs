@@ -3,22 +3,121 @@
 //! This crate provides [`futures`]-based abstractions for asynchronous programming with peripherals from [`stm32f1xx_hal`]:
 //!
 //! - [`AsyncTimer`](crate::timer::AsyncTimer) allows delaying the current task, wrapping [`Timer`](stm32f1xx_hal::timer::Timer).
+//! - [`CompareTimer`](crate::timer::CompareTimer) allows accurate back-to-back delays using a free-running [`Timer`](stm32f1xx_hal::timer::Timer).
 //! - [`TxSink`](crate::serial::TxSink) allows [`Sink`](futures::sink::Sink)-based USART transmissions, wrapping [`TxDma`](stm32f1xx_hal::dma::TxDma).
+//! - [`CoalescingTxSink`](crate::serial::CoalescingTxSink) allows byte-wise [`Sink`](futures::sink::Sink)-based USART transmissions, coalescing bytes into few DMA transfers.
 //! - [`RxStream`](crate::serial::RxStream) allows [`Stream`](futures::stream::Stream)-based USART receives, wrapping [`RxDma`](stm32f1xx_hal::dma::RxDma).
+//! - [`RxTransfer`](crate::serial::RxTransfer) allows [`Future`](core::future::Future)-based one-shot USART receives of a fixed length, wrapping [`RxDma`](stm32f1xx_hal::dma::RxDma).
+//! - [`memcpy`](crate::dma::memcpy) allows DMA-based memory-to-memory copies.
+//! - `AsyncPin` allows awaiting edges on GPIO pins via their EXTI lines.
+//! - `AsyncPvd` allows awaiting the supply voltage falling below a threshold.
+//!
+//! The [`framing`] module provides adapters for splitting received byte streams into frames.
+//! The [`broadcast`] module provides fan-out of streams to several consumers.
+//! The [`time`] module provides delays using a global timer.
+//! The [`stats`] module provides windowed statistics over samples.
 //!
 //! To properly schedule wakeups, this crate implements the following interrupts:
 //!
-//! - [`TIM2`](stm32f1xx_hal::pac::Interrupt::TIM2), [`TIM3`](stm32f1xx_hal::pac::Interrupt::TIM3)
+//! - [`TIM2`](stm32f1xx_hal::pac::Interrupt::TIM2), [`TIM3`](stm32f1xx_hal::pac::Interrupt::TIM3), `TIM4` (with the `medium` feature)
+//! - `TIM1_UP` (with the `stm32f100`, `stm32f103` or `connectivity` feature)
 //! - [`DMA1_CHANNEL4`](stm32f1xx_hal::pac::Interrupt::DMA1_CHANNEL4), [`DMA1_CHANNEL7`](stm32f1xx_hal::pac::Interrupt::DMA1_CHANNEL7), [`DMA1_CHANNEL2`](stm32f1xx_hal::pac::Interrupt::DMA1_CHANNEL2)
 //! - [`DMA1_CHANNEL5`](stm32f1xx_hal::pac::Interrupt::DMA1_CHANNEL5), [`DMA1_CHANNEL6`](stm32f1xx_hal::pac::Interrupt::DMA1_CHANNEL6), [`DMA1_CHANNEL3`](stm32f1xx_hal::pac::Interrupt::DMA1_CHANNEL3)
+//!
+//! The following interrupts are only implemented with the corresponding feature, as they are commonly used otherwise:
+//!
+//! - [`DMA1_CHANNEL1`](stm32f1xx_hal::pac::Interrupt::DMA1_CHANNEL1) with `memcpy` for [`memcpy`](crate::dma::memcpy)
+//! - `EXTI0` to `EXTI4`, `EXTI9_5`, `EXTI15_10` and [`PVD`](stm32f1xx_hal::pac::Interrupt::PVD) with `exti` for the `exti` module
+//! - [`USART1`](stm32f1xx_hal::pac::Interrupt::USART1), [`USART2`](stm32f1xx_hal::pac::Interrupt::USART2), [`USART3`](stm32f1xx_hal::pac::Interrupt::USART3) with `usart-interrupts` for the idle line, CTS and transmission complete events of the [`serial`] module
+//!
+//! The futures of this crate may be polled from any priority, such as by an executor running in an interrupt handler.
+//!
+//! With the `user-interrupts` feature, this crate does not declare any of these interrupts.
+//! Instead, the application declares them and forwards them via [`isr::on_interrupt`].
+//! This avoids conflicts with executors declaring interrupts themselves, such as the interrupt executors of `embassy-executor`, and allows the application to handle further events in the same interrupt handlers.
+//!
+//! The `stm32f100`, `stm32f103` and `connectivity` features enable the advanced-control timer TIM1, which is missing on other devices.
+//! They forward to the features of [`stm32f1xx_hal`] of the same name.
+//! The `medium` feature enables the peripherals of medium-density devices, such as TIM4, and forwards to the feature of [`stm32f1xx_hal`] of the same name.
+//!
+//! With the `systick` feature, this crate also implements the `SysTick` exception for [`AsyncTimer`](crate::timer::AsyncTimer)s of [`SYST`](cortex_m::peripheral::SYST).
+//! It conflicts with any other user of the SysTick exception, such as some executors, unless the application declares it with the `user-interrupts` feature and forwards it via [`isr::on_systick`].
+//!
+//! With the `cortex-m-07` feature, this crate accesses the NVIC via `cortex-m` 0.7 instead of the `cortex-m` 0.6 used by [`stm32f1xx_hal`].
 
 #![no_std]
 #![deny(clippy::all, rust_2018_idioms)]
 #![warn(missing_docs)]
 
-/// Creates a new interrupt waking a [`Waker`].
+/// Logs a trace message via `defmt`, if the `trace` feature is enabled.
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "trace")]
+        defmt::trace!($($arg)+);
+    };
+}
+
+/// Declares the interrupts of this crate, each waking the [`Waker`] registered with [`waker_interrupt!`].
+///
+/// As these interrupts are declared here, they can't be used for anything else.
+/// Each interrupt stores a single waker, so only one future per interrupt can wait at a time, as a second one replaces the waker of the first.
 ///
-/// As this interrupt will be declared in this macro, it can't be used for anything else.
+/// The wakers are stored in [`AtomicWaker`]s, so futures may be polled from any priority, e.g. by an executor running in an interrupt handler.
+/// Each interrupt masks itself after waking, as the peripheral's event flag is only cleared by polling.
+///
+/// With the `user-interrupts` feature, the interrupts are not declared, but handled via [`isr::on_interrupt`].
+///
+/// [`Waker`]: core::task::Waker
+/// [`AtomicWaker`]: futures::task::AtomicWaker
+macro_rules! waker_interrupts {
+    ($($(#[$meta:meta])* $INT:ident),+ $(,)?) => {
+        mod wakers {
+            $(
+                $(#[$meta])*
+                #[allow(non_snake_case)]
+                pub(crate) mod $INT {
+                    use futures::task::AtomicWaker;
+                    #[cfg(not(feature = "user-interrupts"))]
+                    use stm32f1xx_hal::pac::interrupt;
+                    use stm32f1xx_hal::pac::Interrupt;
+
+                    pub(crate) static WAKER: AtomicWaker = AtomicWaker::new();
+
+                    #[cfg(not(feature = "user-interrupts"))]
+                    #[interrupt]
+                    fn $INT() {
+                        handle();
+                    }
+
+                    pub(crate) fn handle() {
+                        trace!("{:str}: waking", stringify!($INT));
+                        WAKER.wake();
+                        crate::nvic::mask(Interrupt::$INT);
+                    }
+                }
+            )+
+
+            /// Handles `interrupt`, returning whether it is one of these interrupts.
+            #[cfg(feature = "user-interrupts")]
+            pub(crate) fn handle(interrupt: stm32f1xx_hal::pac::Interrupt) -> bool {
+                use stm32f1xx_hal::pac::Interrupt;
+
+                match interrupt {
+                    $(
+                        $(#[$meta])*
+                        Interrupt::$INT => $INT::handle(),
+                    )+
+                    _ => return false,
+                }
+                true
+            }
+        }
+    };
+}
+
+/// Registers a [`Waker`] to be woken by an interrupt declared in [`waker_interrupts!`].
+///
+/// `$waker` is a `&Waker`, which is only cloned if it would not wake the same task as the registered one.
 ///
 /// # Examples
 ///
@@ -29,7 +128,7 @@
 ///     if self.is_ready() {
 ///         Poll::Ready(())
 ///     } else {
-///         waker_interrupt!(TIM2, cx.waker().clone());
+///         waker_interrupt!(TIM2, cx.waker());
 ///         Poll::Pending
 ///     }
 /// }
@@ -39,31 +138,83 @@
 /// [`Future::poll`]: core::future::Future::poll
 macro_rules! waker_interrupt {
     ($INT:ident, $waker:expr) => {{
+        use crate::nvic;
         use core::sync::atomic::{self, Ordering};
-        use stm32f1xx_hal::pac::{interrupt, Interrupt, NVIC};
-
-        static mut WAKER: Option<Waker> = None;
+        use stm32f1xx_hal::pac::Interrupt;
 
-        #[interrupt]
-        fn $INT() {
-            // Safety: This context is disabled while the lower priority context accesses WAKER
-            if let Some(waker) = unsafe { WAKER.as_ref() } {
-                waker.wake_by_ref();
-
-                NVIC::mask(Interrupt::$INT);
-            }
-        }
-
-        NVIC::mask(Interrupt::$INT);
+        trace!("{:str}: arming", stringify!($INT));
+        nvic::mask(Interrupt::$INT);
         atomic::compiler_fence(Ordering::Acquire);
-        // Safety: The other relevant context, the interrupt, is disabled
-        unsafe { WAKER = Some($waker) }
-        NVIC::unpend(Interrupt::$INT);
+        crate::wakers::$INT::WAKER.register($waker);
+        nvic::unpend(Interrupt::$INT);
         atomic::compiler_fence(Ordering::Release);
         // Safety: This is the end of a mask-based critical section
-        unsafe { NVIC::unmask(Interrupt::$INT) }
+        unsafe { nvic::unmask(Interrupt::$INT) }
     }};
 }
 
+waker_interrupts!(
+    #[cfg(any(feature = "stm32f100", feature = "stm32f103", feature = "connectivity"))]
+    TIM1_UP,
+    TIM2,
+    TIM3,
+    #[cfg(feature = "medium")]
+    TIM4,
+    #[cfg(feature = "memcpy")]
+    DMA1_CHANNEL1,
+    DMA1_CHANNEL2,
+    DMA1_CHANNEL3,
+    DMA1_CHANNEL4,
+    DMA1_CHANNEL5,
+    DMA1_CHANNEL6,
+    DMA1_CHANNEL7,
+    #[cfg(feature = "exti")]
+    EXTI0,
+    #[cfg(feature = "exti")]
+    EXTI1,
+    #[cfg(feature = "exti")]
+    EXTI2,
+    #[cfg(feature = "exti")]
+    EXTI3,
+    #[cfg(feature = "exti")]
+    EXTI4,
+    #[cfg(feature = "exti")]
+    EXTI9_5,
+    #[cfg(feature = "exti")]
+    EXTI15_10,
+    #[cfg(feature = "exti")]
+    PVD,
+);
+
+/// Records a poll in the [`metrics`] counters, if the `metrics` feature is enabled.
+///
+/// Pending polls need the interrupt to be able to detect spurious wakes.
+/// Ready polls are also traced, while pending polls are traced when arming the interrupt.
+macro_rules! record_poll {
+    ($COUNTERS:ident, Ready) => {
+        trace!("{:str}: ready", stringify!($COUNTERS));
+        #[cfg(feature = "metrics")]
+        crate::metrics::$COUNTERS.ready();
+    };
+    ($COUNTERS:ident, Pending, $INT:ident) => {
+        #[cfg(feature = "metrics")]
+        crate::metrics::$COUNTERS
+            .pending(crate::nvic::is_enabled(stm32f1xx_hal::pac::Interrupt::$INT));
+    };
+}
+
+pub mod broadcast;
+pub mod dma;
+#[cfg(feature = "exti")]
+pub mod exti;
+pub mod framing;
+pub mod idle;
+pub mod isr;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod nvic;
 pub mod serial;
+pub mod stats;
+pub mod sync;
+pub mod time;
 pub mod timer;
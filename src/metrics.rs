@@ -0,0 +1,78 @@
+//! Poll-level instrumentation counters for diagnostics.
+//!
+//! This module is only available with the `metrics` feature.
+//! Without it, no counting code is compiled in.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Poll counters of one kind of future.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FutureMetrics {
+    /// The number of polls returning [`Poll::Ready`](core::task::Poll::Ready).
+    pub ready: u32,
+    /// The number of polls returning [`Poll::Pending`](core::task::Poll::Pending).
+    pub pending: u32,
+    /// The number of polls returning [`Poll::Pending`](core::task::Poll::Pending) while the interrupt had still been armed.
+    ///
+    /// These polls have not been caused by this crate's interrupts but by spurious wakes, such as from other branches of a `select!`.
+    pub spurious: u32,
+}
+
+/// A snapshot of all poll counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Counters of [`Delay`](crate::timer::Delay).
+    pub delay: FutureMetrics,
+    /// Counters of [`TransferFuture`](crate::serial::TransferFuture).
+    pub transfer: FutureMetrics,
+    /// Counters of [`RxStream`](crate::serial::RxStream).
+    pub rx_stream: FutureMetrics,
+}
+
+pub(crate) struct Counters {
+    ready: AtomicU32,
+    pending: AtomicU32,
+    spurious: AtomicU32,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            ready: AtomicU32::new(0),
+            pending: AtomicU32::new(0),
+            spurious: AtomicU32::new(0),
+        }
+    }
+
+    pub(crate) fn ready(&self) {
+        self.ready.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn pending(&self, armed: bool) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if armed {
+            self.spurious.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self) -> FutureMetrics {
+        FutureMetrics {
+            ready: self.ready.load(Ordering::Relaxed),
+            pending: self.pending.load(Ordering::Relaxed),
+            spurious: self.spurious.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub(crate) static DELAY: Counters = Counters::new();
+pub(crate) static TRANSFER: Counters = Counters::new();
+pub(crate) static RX_STREAM: Counters = Counters::new();
+
+/// Returns a snapshot of all poll counters.
+pub fn metrics() -> Metrics {
+    Metrics {
+        delay: DELAY.snapshot(),
+        transfer: TRANSFER.snapshot(),
+        rx_stream: RX_STREAM.snapshot(),
+    }
+}
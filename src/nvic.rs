@@ -0,0 +1,61 @@
+//! A shim over the NVIC API of `cortex-m`.
+//!
+//! All NVIC accesses of this crate go through these functions, which take the concrete [`Interrupt`] of the device.
+//! By default, they forward to the `cortex-m` version used by [`stm32f1xx_hal`].
+//! With the `cortex-m-07` feature, they use the NVIC of `cortex-m` 0.7 instead, whose methods are generic over `InterruptNumber` instead of `Nr`.
+
+#[cfg(feature = "cortex-m-07")]
+use cortex_m::interrupt::Nr;
+#[cfg(not(feature = "cortex-m-07"))]
+use cortex_m::peripheral::NVIC;
+#[cfg(feature = "cortex-m-07")]
+use cortex_m_07::{interrupt::InterruptNumber, peripheral::NVIC};
+use stm32f1xx_hal::pac::Interrupt;
+
+/// The number of an [`Interrupt`] as expected by `cortex-m` 0.7.
+#[cfg(feature = "cortex-m-07")]
+#[derive(Clone, Copy)]
+struct Number(u8);
+
+// Safety: The number is taken from the device's `Interrupt`, so it is a valid interrupt number
+#[cfg(feature = "cortex-m-07")]
+unsafe impl InterruptNumber for Number {
+    fn number(self) -> u16 {
+        self.0.into()
+    }
+}
+
+#[cfg(not(feature = "cortex-m-07"))]
+fn nr(interrupt: Interrupt) -> Interrupt {
+    interrupt
+}
+
+#[cfg(feature = "cortex-m-07")]
+fn nr(interrupt: Interrupt) -> Number {
+    Number(interrupt.nr())
+}
+
+/// Disables `interrupt`.
+pub(crate) fn mask(interrupt: Interrupt) {
+    NVIC::mask(nr(interrupt));
+}
+
+/// Enables `interrupt`.
+///
+/// # Safety
+///
+/// This can break mask-based critical sections.
+pub(crate) unsafe fn unmask(interrupt: Interrupt) {
+    NVIC::unmask(nr(interrupt));
+}
+
+/// Clears the pending state of `interrupt`.
+pub(crate) fn unpend(interrupt: Interrupt) {
+    NVIC::unpend(nr(interrupt));
+}
+
+/// Returns whether `interrupt` is enabled.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+pub(crate) fn is_enabled(interrupt: Interrupt) -> bool {
+    NVIC::is_enabled(nr(interrupt))
+}
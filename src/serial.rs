@@ -1,24 +1,142 @@
 //! [`Stream`]/[`Sink`]-based abstractions for DMA-based Serial Communication (USART).
 
-use as_slice::AsMutSlice;
+#[cfg(feature = "usart-interrupts")]
+use crate::framing;
+use crate::{
+    sync::{Mutex, MutexGuard},
+    timer::{AsyncTimer, Delay, Hz, Instance, Instant, Ticks},
+};
+use as_slice::{AsMutSlice, AsSlice};
 use core::{
-    convert::Infallible,
+    convert::{Infallible, TryFrom},
     future::Future,
+    mem,
+    ops::{Deref, DerefMut},
     pin::Pin,
-    task::{Context, Poll, Waker},
+    ptr, slice,
+    task::{Context, Poll},
 };
 use embedded_dma::{StaticReadBuffer, StaticWriteBuffer};
+use embedded_hal::digital::v2::OutputPin;
+#[cfg(feature = "usart-interrupts")]
+use embedded_hal::digital::v2::{InputPin, PinState};
+#[cfg(feature = "usart-interrupts")]
+use futures::future::FutureExt;
 use futures::{
+    future::{self, FusedFuture},
+    ready,
     sink::{Sink, SinkExt},
-    stream::{FusedStream, Stream},
+    stream::{FusedStream, Stream, StreamExt},
 };
+use heapless::Vec;
 use stm32f1xx_hal::{
-    dma::{self, CircBuffer, CircReadDma, Event, Half, Transfer, TransferPayload, WriteDma, R},
-    serial::{RxDma1, RxDma2, RxDma3, TxDma1, TxDma2, TxDma3},
+    dma::{
+        self, CircBuffer, CircReadDma, Event, Half, ReadDma, Transfer, TransferPayload, WriteDma,
+        R, W,
+    },
+    pac::{DMA1, USART1, USART2, USART3},
+    serial::{RxDma1, RxDma2, RxDma3, Serial, TxDma1, TxDma2, TxDma3},
 };
 
+/// Declares the USART interrupts, each waking the [`Waker`]s of the events it has been enabled for.
+///
+/// Unlike the interrupts of `waker_interrupts!`, a USART interrupt is raised by several independent event sources.
+/// Thus, the receiver events (received data and idle line) and transmitter events (transmission complete and CTS) each have their own [`AtomicWaker`].
+/// The interrupt disables the enable bits of the events of each waker it wakes, instead of masking itself.
+/// This keeps the events of the other waker armed.
+///
+/// The enable bits are only modified by the interrupt or via `modify_masked`, which masks the interrupt for the duration of the modification.
+///
+/// [`Waker`]: core::task::Waker
+/// [`AtomicWaker`]: futures::task::AtomicWaker
+#[cfg(feature = "usart-interrupts")]
+macro_rules! usart_interrupts {
+    ($($USARTX:ident,)+) => {
+        pub(crate) mod usart_wakers {
+            $(
+                #[allow(non_snake_case)]
+                pub(crate) mod $USARTX {
+                    use crate::nvic;
+                    use core::sync::atomic::{self, Ordering};
+                    use futures::task::AtomicWaker;
+                    #[cfg(not(feature = "user-interrupts"))]
+                    use stm32f1xx_hal::pac::interrupt;
+                    use stm32f1xx_hal::pac::{usart1::RegisterBlock, Interrupt, $USARTX};
+
+                    /// The waker of the receiver events, i.e., received data and idle line.
+                    pub(crate) static RX_WAKER: AtomicWaker = AtomicWaker::new();
+
+                    /// The waker of the transmitter events, i.e., transmission complete and CTS.
+                    pub(crate) static TX_WAKER: AtomicWaker = AtomicWaker::new();
+
+                    /// Modifies the registers of the USART without racing with the interrupt, which leaves it unmasked.
+                    pub(crate) fn modify_masked(f: impl FnOnce(&RegisterBlock)) {
+                        trace!("{:str}: modifying", stringify!($USARTX));
+                        nvic::mask(Interrupt::$USARTX);
+                        atomic::compiler_fence(Ordering::Acquire);
+                        // Safety: The USART is owned by the caller and its interrupt is masked
+                        f(unsafe { &*$USARTX::ptr() });
+                        atomic::compiler_fence(Ordering::Release);
+                        // Safety: This is the end of a mask-based critical section
+                        unsafe { nvic::unmask(Interrupt::$USARTX) }
+                    }
+
+                    #[cfg(not(feature = "user-interrupts"))]
+                    #[interrupt]
+                    fn $USARTX() {
+                        handle();
+                    }
+
+                    pub(crate) fn handle() {
+                        // Safety: Only the enable bits of the events of woken wakers are cleared
+                        let usart = unsafe { &*$USARTX::ptr() };
+                        let sr = usart.sr.read();
+                        let cr1 = usart.cr1.read();
+                        let cr3 = usart.cr3.read();
+
+                        // The DMA may have read the received byte already, clearing RXNE
+                        let rx = cr1.rxneie().bit_is_set()
+                            || cr1.idleie().bit_is_set() && sr.idle().bit_is_set();
+                        if rx {
+                            trace!("{:str}: waking receiver", stringify!($USARTX));
+                            usart.cr1.modify(|_, w| w.rxneie().clear_bit().idleie().clear_bit());
+                            RX_WAKER.wake();
+                        }
+
+                        let tx = cr1.tcie().bit_is_set() && sr.tc().bit_is_set()
+                            || cr3.ctsie().bit_is_set() && sr.cts().bit_is_set();
+                        if tx {
+                            trace!("{:str}: waking transmitter", stringify!($USARTX));
+                            usart.cr1.modify(|_, w| w.tcie().clear_bit());
+                            usart.cr3.modify(|_, w| w.ctsie().clear_bit());
+                            TX_WAKER.wake();
+                        }
+                    }
+                }
+            )+
+
+            /// Handles `interrupt`, returning whether it is one of these interrupts.
+            #[cfg(feature = "user-interrupts")]
+            pub(crate) fn handle(interrupt: stm32f1xx_hal::pac::Interrupt) -> bool {
+                use stm32f1xx_hal::pac::Interrupt;
+
+                match interrupt {
+                    $(Interrupt::$USARTX => $USARTX::handle(),)+
+                    _ => return false,
+                }
+                true
+            }
+        }
+    };
+}
+
+#[cfg(feature = "usart-interrupts")]
+usart_interrupts!(USART1, USART2, USART3,);
+
 /// A [`Future`] driving a [`Transfer`].
 ///
+/// Polling it after completion returns [`Poll::Pending`] instead of panicking.
+///
 /// You can not use this directly.
 /// Use [`TxSink`] instead.
 #[must_use = "futures do nothing unless you `.await` or poll them"]
@@ -33,9 +151,38 @@ impl<T> TransferFuture<T> {
 
 macro_rules! transfer_future {
     ($(
-        $USARTX:ident: ($INT:ident, $TxDmaX:ty),
+        $USARTX:ident: ($INT:ident, $chX:ident, $TxDmaX:ty),
     )+) => {
         $(
+            impl<BUF> TransferFuture<Transfer<R, BUF, $TxDmaX>> {
+                /// Stops the transfer immediately, returning the number of remaining transfers.
+                fn abort(self) -> (u16, BUF, $TxDmaX) {
+                    let transfer = self.0.expect("aborted after completion");
+                    // Safety: The channel is owned by this transfer
+                    let ch = unsafe { &(*DMA1::ptr()).$chX };
+                    ch.cr.modify(|_, w| w.en().clear_bit());
+                    let remaining = ch.ndtr.read().ndt().bits();
+                    if remaining == 0 {
+                        let (buf, tx) = transfer.wait();
+                        return (0, buf, tx);
+                    }
+
+                    // Transfer::wait only returns on TransferComplete.
+                    // We provoke it by a single memory-to-memory transfer to a scratch byte.
+                    let par = ch.par.read().bits();
+                    let mut scratch = 0u8;
+                    // Safety: scratch outlives the DMA transfer, as we wait for it
+                    ch.par.write(|w| unsafe { w.bits(&mut scratch as *mut u8 as u32) });
+                    ch.ndtr.write(|w| w.ndt().bits(1));
+                    ch.cr.modify(|_, w| w.mem2mem().set_bit().en().set_bit());
+                    let (buf, tx) = transfer.wait();
+                    ch.cr.modify(|_, w| w.mem2mem().clear_bit());
+                    // Safety: This is the previous peripheral address
+                    ch.par.write(|w| unsafe { w.bits(par) });
+                    (remaining, buf, tx)
+                }
+            }
+
             impl<BUF> Future for TransferFuture<Transfer<R, BUF, $TxDmaX>>
             where
                 BUF: Unpin,
@@ -43,25 +190,53 @@ macro_rules! transfer_future {
                 type Output = (BUF, $TxDmaX);
 
                 fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-                    let transfer = self.0.as_mut().expect("polled after completion");
+                    let transfer = match self.0.as_mut() {
+                        Some(transfer) => transfer,
+                        // Polled after completion, see FusedFuture
+                        None => return Poll::Pending,
+                    };
                     if transfer.is_done() {
+                        record_poll!(TRANSFER, Ready);
                         Poll::Ready(self.0.take().unwrap().wait())
                     } else {
-                        waker_interrupt!($INT, cx.waker().clone());
+                        record_poll!(TRANSFER, Pending, $INT);
+                        waker_interrupt!($INT, cx.waker());
                         Poll::Pending
                     }
                 }
             }
+
+            impl<BUF> FusedFuture for TransferFuture<Transfer<R, BUF, $TxDmaX>>
+            where
+                BUF: Unpin,
+            {
+                fn is_terminated(&self) -> bool {
+                    self.0.is_none()
+                }
+            }
         )+
     }
 }
 
 transfer_future!(
-    USART1: (DMA1_CHANNEL4, TxDma1),
-    USART2: (DMA1_CHANNEL7, TxDma2),
-    USART3: (DMA1_CHANNEL2, TxDma3),
+    USART1: (DMA1_CHANNEL4, ch4, TxDma1),
+    USART2: (DMA1_CHANNEL7, ch7, TxDma2),
+    USART3: (DMA1_CHANNEL2, ch2, TxDma3),
 );
 
+mod sealed {
+    pub trait TxPayload {}
+}
+
+/// A DMA transmitter of a USART, i.e., [`TxDma1`], [`TxDma2`] or [`TxDma3`].
+pub trait TxPayload: sealed::TxPayload {
+    /// Clears the transmission complete flag of the USART, which is required before starting a transfer.
+    ///
+    /// The DMA does not clear the flag when writing DR, so it would be stale from the previous transfer otherwise.
+    #[doc(hidden)]
+    fn clear_tc();
+}
+
 /// A [`Sink`]-based asynchronous abstraction over a DMA transmitter.
 ///
 /// # Examples
@@ -84,6 +259,38 @@ enum TxSinkState<'a, BUF, PAYLOAD: TransferPayload> {
     Sending {
         transfer: TransferFuture<Transfer<R, &'a mut BUF, PAYLOAD>>,
     },
+    SendingStatic {
+        transfer: TransferFuture<Transfer<R, &'static [u8], PAYLOAD>>,
+        buf: &'a mut BUF,
+    },
+}
+
+impl<'a, BUF, PAYLOAD> TxSink<'a, BUF, PAYLOAD>
+where
+    PAYLOAD: TransferPayload,
+{
+    /// Creates a new [`TxSink`] from the specified buffer and DMA transmitter, the channel of which must be listening to [`Event::TransferComplete`].
+    ///
+    /// This is useful for custom DMA configurations.
+    /// Otherwise, use `new` instead.
+    pub fn from_listening(buf: &'a mut BUF, tx: PAYLOAD) -> Self {
+        Self(Some(TxSinkState::Ready { buf, tx }))
+    }
+
+    /// Runs `f` after each completed transfer.
+    ///
+    /// `f` is run in the task context, when the sink is polled after the transfer has completed.
+    /// This is useful for switching an RS-485 driver back to receiving or turning off an activity LED.
+    pub fn on_complete<F>(self, f: F) -> OnComplete<Self, F>
+    where
+        F: FnMut(),
+    {
+        OnComplete {
+            sink: self,
+            f,
+            sending: false,
+        }
+    }
 }
 
 impl<'a, BUF, PAYLOAD> TxSink<'a, BUF, PAYLOAD>
@@ -105,9 +312,10 @@ where
 impl<BUF, PAYLOAD> Sink<BUF> for TxSink<'static, BUF, PAYLOAD>
 where
     &'static mut BUF: StaticReadBuffer<Word = u8>,
-    PAYLOAD: WriteDma<&'static mut BUF, u8> + Unpin,
+    PAYLOAD: WriteDma<&'static mut BUF, u8> + TxPayload + Unpin,
     TransferFuture<Transfer<R, &'static mut BUF, PAYLOAD>>:
         Future<Output = (&'static mut BUF, PAYLOAD)>,
+    TransferFuture<Transfer<R, &'static [u8], PAYLOAD>>: Future<Output = (&'static [u8], PAYLOAD)>,
 {
     type Error = Infallible;
 
@@ -120,11 +328,14 @@ where
         match this {
             TxSinkState::Ready { tx, buf } => {
                 *buf = item;
+                PAYLOAD::clear_tc();
                 let transfer = TransferFuture::from_listening(tx.write(buf));
                 self.0 = Some(TxSinkState::Sending { transfer });
                 Ok(())
             }
-            TxSinkState::Sending { .. } => panic!("started sending before polled ready"),
+            TxSinkState::Sending { .. } | TxSinkState::SendingStatic { .. } => {
+                panic!("started sending before polled ready")
+            }
         }
     }
 
@@ -138,6 +349,16 @@ where
                     Poll::Ready(Ok(()))
                 }
             },
+            TxSinkState::SendingStatic { transfer, .. } => match Pin::new(transfer).poll(cx) {
+                Poll::Pending => Poll::Pending,
+                Poll::Ready((_, tx)) => match self.0.take().unwrap() {
+                    TxSinkState::SendingStatic { buf, .. } => {
+                        self.0 = Some(TxSinkState::Ready { tx, buf });
+                        Poll::Ready(Ok(()))
+                    }
+                    _ => unreachable!(),
+                },
+            },
         }
     }
 
@@ -146,11 +367,78 @@ where
     }
 }
 
+/// A variable-length frame for a [`TxSink`], of which only the populated bytes are transmitted.
+///
+/// # Examples
+///
+/// ```
+/// static mut TX_BUF: TxFrame<64> = TxFrame::new();
+/// let mut tx_sink = TxSink3::new(unsafe { &mut TX_BUF }, tx.with_dma(channels.2));
+/// let mut frame = TxFrame::new();
+/// frame.extend_from_slice(b"Hello").unwrap();
+/// tx_sink.send(frame).await.unwrap();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TxFrame<const N: usize>(pub Vec<u8, N>);
+
+impl<const N: usize> TxFrame<N> {
+    /// Creates a new, empty [`TxFrame`].
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<const N: usize> From<Vec<u8, N>> for TxFrame<N> {
+    fn from(vec: Vec<u8, N>) -> Self {
+        Self(vec)
+    }
+}
+
+impl<const N: usize> Deref for TxFrame<N> {
+    type Target = Vec<u8, N>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<const N: usize> DerefMut for TxFrame<N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<const N: usize> AsSlice for TxFrame<N> {
+    type Element = u8;
+
+    fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for TxFrame<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 macro_rules! tx_sink {
     ($(
-        $TxSinkX:ident: ($TxDmaX:ty),
+        $TxSinkX:ident, $CoalescingTxSinkX:ident: ($USARTX:ident, $TxDmaX:ty),
     )+) => {
         $(
+            impl sealed::TxPayload for $TxDmaX {}
+
+            impl TxPayload for $TxDmaX {
+                fn clear_tc() {
+                    // Safety: We own the transmitter of this peripheral.
+                    // Writing 1 does not clear the other flags, unlike a read-modify-write, which could clear flags set in between.
+                    unsafe { &*$USARTX::ptr() }
+                        .sr
+                        .write(|w| w.lbd().set_bit().rxne().set_bit().cts().set_bit().tc().clear_bit());
+                }
+            }
+
             /// A type shorthand for specifying different DMA channels easily.
             pub type $TxSinkX<'a, BUF> = TxSink<'a, BUF, $TxDmaX>;
 
@@ -158,8 +446,155 @@ macro_rules! tx_sink {
                 /// Creates a new [`TxSink`] from the specified buffer and DMA transmitter.
                 pub fn new(buf: &'a mut BUF, mut tx: $TxDmaX) -> Self {
                     tx.channel.listen(Event::TransferComplete);
-                    Self(Some(TxSinkState::Ready {
+                    Self::from_listening(buf, tx)
+                }
+
+                /// Aborts the transfer in flight, if any, discarding the rest of it.
+                ///
+                /// Returns the number of bytes of the aborted transfer which have already been handed to the USART.
+                pub fn abort(&mut self) -> usize
+                where
+                    BUF: AsSlice<Element = u8>,
+                {
+                    match self.0.take().unwrap() {
+                        TxSinkState::Sending { transfer } => {
+                            let (remaining, buf, tx) = transfer.abort();
+                            let sent = buf.as_slice().len() - usize::from(remaining);
+                            self.0 = Some(TxSinkState::Ready { buf, tx });
+                            sent
+                        }
+                        TxSinkState::SendingStatic { transfer, buf } => {
+                            let (remaining, data, tx) = transfer.abort();
+                            let sent = data.len() - usize::from(remaining);
+                            self.0 = Some(TxSinkState::Ready { buf, tx });
+                            sent
+                        }
+                        ready => {
+                            self.0 = Some(ready);
+                            0
+                        }
+                    }
+                }
+            }
+
+            impl<'a, BUF> $TxSinkX<'a, BUF>
+            where
+                $TxSinkX<'a, BUF>: Sink<BUF, Error = Infallible>,
+            {
+                /// Transmits `data` directly from its location, without copying it into the buffer of this sink.
+                ///
+                /// The DMA can read from flash on the STM32F1, so this is useful for sending constant strings and tables without occupying RAM.
+                /// The data is transferred byte-wise, so there are no alignment constraints.
+                /// Items sent before are transmitted first.
+                ///
+                /// If the returned [`Future`] is dropped, the transfer continues and is awaited by the next flush of this sink.
+                ///
+                /// # Examples
+                ///
+                /// ```
+                /// static BANNER: &[u8] = b"async-stm32f1xx\r\n";
+                /// tx_sink.send_static(BANNER).await;
+                /// ```
+                pub async fn send_static(&mut self, data: &'static [u8]) {
+                    // Unwrapping: TxSink is infallible
+                    self.flush().await.unwrap();
+                    match self.0.take().unwrap() {
+                        TxSinkState::Ready { buf, tx } => {
+                            <$TxDmaX>::clear_tc();
+                            let transfer = TransferFuture::from_listening(tx.write(data));
+                            self.0 = Some(TxSinkState::SendingStatic { transfer, buf });
+                        }
+                        _ => unreachable!("invalid state after flushing"),
+                    }
+                    self.flush().await.unwrap();
+                }
+
+                /// Transmits all bytes completely and disables the transmitter, releasing the buffer and DMA transmitter.
+                ///
+                /// Unlike [`release`](TxSink::release), this also waits for the last frame to leave the shift register.
+                /// This is useful for gating the USART's clock, e.g. before entering a low-power mode, without truncating the last byte.
+                ///
+                /// The last frame is awaited by the USART's transmission complete interrupt.
+                /// The flag is cleared before each transfer, so it is only set after the last frame of the last transfer.
+                ///
+                /// This requires the `usart-interrupts` feature.
+                #[cfg(feature = "usart-interrupts")]
+                pub async fn shutdown(self) -> (&'a mut BUF, $TxDmaX) {
+                    let (buf, tx) = self.release().await;
+
+                    // Safety: We own the TxSink for this peripheral
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    future::poll_fn(|cx| {
+                        if usart.sr.read().tc().bit_is_set() {
+                            Poll::Ready(())
+                        } else {
+                            usart_wakers::$USARTX::TX_WAKER.register(cx.waker());
+                            usart_wakers::$USARTX::modify_masked(|usart| usart.cr1.modify(|_, w| w.tcie().set_bit()));
+                            Poll::Pending
+                        }
+                    })
+                    .await;
+                    usart_wakers::$USARTX::modify_masked(|usart| usart.cr1.modify(|_, w| w.tcie().clear_bit().te().clear_bit()));
+
+                    (buf, tx)
+                }
+            }
+
+            #[cfg(feature = "usart-interrupts")]
+            impl<BUF> $TxSinkX<'_, BUF> {
+                /// Waits for the nCTS input to toggle, returning its new level read from `cts`.
+                ///
+                /// This is driven by the USART's CTS interrupt, which requires hardware flow control (CTSE) to be enabled.
+                /// As nCTS is active low, [`PinState::Low`] means that the peer is ready to receive.
+                /// This is useful for custom flow control policies or for detecting a peer becoming ready.
+                ///
+                /// This requires the `usart-interrupts` feature.
+                pub async fn wait_cts_change<P>(&mut self, cts: &P) -> Result<PinState, P::Error>
+                where
+                    P: InputPin,
+                {
+                    // Safety: We own the TxSink for this peripheral and only access the CTS bits
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    // The other flags are not cleared by writing 1, unlike a read-modify-write, which could clear flags set in between
+                    let clear_cts = || usart.sr.write(|w| w.lbd().set_bit().tc().set_bit().rxne().set_bit().cts().clear_bit());
+                    // Discard toggles from before waiting
+                    clear_cts();
+                    future::poll_fn(|cx| {
+                        if usart.sr.read().cts().bit_is_set() {
+                            clear_cts();
+                            Poll::Ready(())
+                        } else {
+                            usart_wakers::$USARTX::TX_WAKER.register(cx.waker());
+                            usart_wakers::$USARTX::modify_masked(|usart| usart.cr3.modify(|_, w| w.ctsie().set_bit()));
+                            Poll::Pending
+                        }
+                    })
+                    .await;
+                    usart_wakers::$USARTX::modify_masked(|usart| usart.cr3.modify(|_, w| w.ctsie().clear_bit()));
+
+                    if cts.is_high()? {
+                        Ok(PinState::High)
+                    } else {
+                        Ok(PinState::Low)
+                    }
+                }
+            }
+
+            /// A type shorthand for specifying different DMA channels easily.
+            pub type $CoalescingTxSinkX = CoalescingTxSink<$TxDmaX>;
+
+            impl $CoalescingTxSinkX {
+                /// Creates a new [`CoalescingTxSink`] from the specified buffer and DMA transmitter.
+                ///
+                /// # Panics
+                ///
+                /// Panics if `buf` is empty.
+                pub fn new(buf: &'static mut [u8], mut tx: $TxDmaX) -> Self {
+                    assert!(!buf.is_empty(), "buffer is empty");
+                    tx.channel.listen(Event::TransferComplete);
+                    Self(Some(CoalescingTxSinkState::Ready {
                         buf,
+                        len: 0,
                         tx,
                     }))
                 }
@@ -168,108 +603,1885 @@ macro_rules! tx_sink {
     }
 }
 
-tx_sink!(TxSink1: (TxDma1), TxSink2: (TxDma2), TxSink3: (TxDma3),);
+tx_sink!(
+    TxSink1, CoalescingTxSink1: (USART1, TxDma1),
+    TxSink2, CoalescingTxSink2: (USART2, TxDma2),
+    TxSink3, CoalescingTxSink3: (USART3, TxDma3),
+);
 
-/// A [`Stream`]-based asynchronous abstraction over a DMA receiver.
+/// A [`Sink`]-based asynchronous abstraction over a DMA transmitter, coalescing bytes into few transfers.
+///
+/// Sent bytes are accumulated in the buffer until it is full or the sink is flushed.
+/// Only then, the accumulated bytes are transmitted in a single DMA transfer.
 ///
 /// # Examples
 ///
 /// ```
-/// let mut tx_sink = TxSink3::new(tx_buf, tx.with_dma(channels.2));
-/// let mut rx_stream = RxStream3::new(rx_buf, rx.with_dma(channels.3));
-/// // Echoes USART3, by sending all items from the infinite RxStream
-/// tx_sink.send_all(&mut rx_stream).await?;
-/// unreachable!("rx_stream is empty");
+/// let mut tx_sink = CoalescingTxSink3::new(tx_buf, tx.with_dma(channels.2));
+/// // Transmits "01234567" in one transfer
+/// tx_sink.send_all(&mut stream::iter(b"01234567".iter().copied().map(Ok))).await.unwrap();
 /// ```
-#[must_use = "streams do nothing unless polled"]
-pub struct RxStream<BUF, PAYLOAD>
+#[must_use = "sinks do nothing unless polled"]
+pub struct CoalescingTxSink<PAYLOAD: TransferPayload>(Option<CoalescingTxSinkState<PAYLOAD>>);
+
+enum CoalescingTxSinkState<PAYLOAD: TransferPayload> {
+    Ready {
+        buf: &'static mut [u8],
+        len: usize,
+        tx: PAYLOAD,
+    },
+    Sending {
+        transfer: TransferFuture<Transfer<R, &'static mut [u8], PAYLOAD>>,
+        /// The pointer to and the length of the whole buffer, from which the transferred head has been derived.
+        buf: (*mut u8, usize),
+    },
+}
+
+impl<PAYLOAD> CoalescingTxSink<PAYLOAD>
 where
-    BUF: 'static,
+    PAYLOAD: WriteDma<&'static mut [u8], u8> + TxPayload + Unpin,
+    TransferFuture<Transfer<R, &'static mut [u8], PAYLOAD>>:
+        Future<Output = (&'static mut [u8], PAYLOAD)>,
 {
-    circ_buffer: CircBuffer<BUF, PAYLOAD>,
-    last_read_half: Half,
-}
+    /// Releases the buffer and payload peripheral after transmitting all accumulated bytes.
+    pub async fn release(mut self) -> (&'static mut [u8], PAYLOAD) {
+        // Unwrapping: CoalescingTxSink is infallible
+        self.close().await.unwrap();
+        match self.0.unwrap() {
+            CoalescingTxSinkState::Ready { buf, tx, .. } => (buf, tx),
+            _ => unreachable!("invalid state after closing"),
+        }
+    }
 
-macro_rules! rx_stream {
-    ($(
-        $RxStreamX:ident: ($INT:ident, $rxdma:ty),
-    )+) => {
-        $(
-            /// A type shorthand for specifying different DMA channels easily.
-            pub type $RxStreamX<BUF> = RxStream<BUF, $rxdma>;
+    /// Returns the number of bytes which can be accumulated before a transmission is forced.
+    ///
+    /// This is useful for not splitting a frame across transmissions.
+    /// While a transmission is running, no bytes are accepted, so `0` is returned.
+    pub fn remaining_capacity(&self) -> usize {
+        match self.0.as_ref().unwrap() {
+            CoalescingTxSinkState::Ready { buf, len, .. } => buf.len() - len,
+            CoalescingTxSinkState::Sending { .. } => 0,
+        }
+    }
 
-            impl<BUF> $RxStreamX<BUF>
-            where
-                &'static mut [BUF; 2]: StaticWriteBuffer<Word = u8>,
-            {
-                /// Creates a new [`RxStream`] from the specified buffers and DMA transmitter.
-                pub fn new(buf: &'static mut [BUF; 2], mut rx: $rxdma) -> Self
-                where
-                    BUF: AsMutSlice<Element = u8>,
-                {
-                    rx.channel.listen(Event::HalfTransfer);
-                    rx.channel.listen(Event::TransferComplete);
-                    Self {
-                        circ_buffer: rx.circ_read(buf),
-                        last_read_half: Half::Second,
+    /// Transmits `bufs` back-to-back, e.g. a header, a payload and a checksum without concatenating them first.
+    ///
+    /// The slices are staged into the buffer, so they are transmitted in a single transfer if they fit.
+    /// Otherwise, the wire output only pauses for restarting the DMA each time the buffer is full.
+    /// The sink is flushed afterwards.
+    pub async fn send_vectored(&mut self, bufs: &[&[u8]]) {
+        for &bytes in bufs {
+            let mut bytes = bytes;
+            while !bytes.is_empty() {
+                // Unwrapping: CoalescingTxSink is infallible
+                future::poll_fn(|cx| Pin::new(&mut *self).poll_ready(cx))
+                    .await
+                    .unwrap();
+                match self.0.as_mut().unwrap() {
+                    CoalescingTxSinkState::Ready { buf, len, .. } => {
+                        let n = bytes.len().min(buf.len() - *len);
+                        buf[*len..*len + n].copy_from_slice(&bytes[..n]);
+                        *len += n;
+                        bytes = &bytes[n..];
+                    }
+                    CoalescingTxSinkState::Sending { .. } => {
+                        unreachable!("sending after polled ready")
                     }
                 }
+            }
+        }
+        self.flush().await.unwrap();
+    }
 
-                /// Releases the buffers and DMA transmitter.
-                pub fn release(self) -> (&'static mut [BUF; 2], $rxdma) {
-                    self.circ_buffer.stop()
-                }
+    fn is_full(&self) -> bool {
+        match self.0.as_ref().unwrap() {
+            CoalescingTxSinkState::Ready { buf, len, .. } => *len == buf.len(),
+            CoalescingTxSinkState::Sending { .. } => false,
+        }
+    }
+
+    /// Starts transmitting the accumulated bytes, if any.
+    fn start_transfer(&mut self) {
+        match self.0.take().unwrap() {
+            CoalescingTxSinkState::Ready { buf, len, tx } if len > 0 => {
+                let buf = (buf.as_mut_ptr(), buf.len());
+                // Safety: The head is within the buffer, which is not accessed until the transfer has returned the head
+                let head = unsafe { slice::from_raw_parts_mut(buf.0, len) };
+                PAYLOAD::clear_tc();
+                let transfer = TransferFuture::from_listening(tx.write(head));
+                self.0 = Some(CoalescingTxSinkState::Sending { transfer, buf });
             }
+            this => self.0 = Some(this),
+        }
+    }
 
-            impl<BUF> Stream for $RxStreamX<BUF>
-            where
-                BUF: Clone,
-            {
-                type Item = Result<BUF, dma::Error>;
+    /// Drives a running transfer to completion, if any.
+    fn poll_transfer(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(CoalescingTxSinkState::Sending { transfer, .. }) = &mut self.0 {
+            let (_head, tx) = ready!(Pin::new(transfer).poll(cx));
+            let (ptr, len) = match self.0.take() {
+                Some(CoalescingTxSinkState::Sending { buf, .. }) => buf,
+                _ => unreachable!(),
+            };
+            // Safety: The pointer covers the whole buffer, and the head derived from it has been returned by the transfer
+            let buf = unsafe { slice::from_raw_parts_mut(ptr, len) };
+            self.0 = Some(CoalescingTxSinkState::Ready { buf, len: 0, tx });
+        }
+        Poll::Ready(())
+    }
+}
 
-                fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-                    let last_read_half = self.last_read_half;
-                    let res = self.circ_buffer.peek(|buf, half| {
-                        if half == last_read_half {
-                            None
-                        } else {
-                            Some((buf.clone(), half))
-                        }
-                    });
+impl<PAYLOAD> Sink<u8> for CoalescingTxSink<PAYLOAD>
+where
+    PAYLOAD: WriteDma<&'static mut [u8], u8> + TxPayload + Unpin,
+    TransferFuture<Transfer<R, &'static mut [u8], PAYLOAD>>:
+        Future<Output = (&'static mut [u8], PAYLOAD)>,
+{
+    type Error = Infallible;
 
-                    match res {
-                        Ok(Some((buf, half))) => {
-                            self.last_read_half = half;
-                            Poll::Ready(Some(Ok(buf)))
-                        }
-                        Ok(None) => {
-                            waker_interrupt!($INT, cx.waker().clone());
-                            Poll::Pending
-                        }
-                        Err(err) => Poll::Ready(Some(Err(err))),
-                    }
-                }
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_transfer(cx));
+        if this.is_full() {
+            this.start_transfer();
+            ready!(this.poll_transfer(cx));
+        }
+        Poll::Ready(Ok(()))
+    }
 
-                fn size_hint(&self) -> (usize, Option<usize>) {
-                    (usize::MAX, None)
-                }
+    fn start_send(mut self: Pin<&mut Self>, item: u8) -> Result<(), Self::Error> {
+        match self.0.as_mut().unwrap() {
+            CoalescingTxSinkState::Ready { buf, len, .. } => {
+                buf[*len] = item;
+                *len += 1;
+                Ok(())
             }
+            CoalescingTxSinkState::Sending { .. } => panic!("started sending before polled ready"),
+        }
+    }
 
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_transfer(cx));
+        this.start_transfer();
+        ready!(this.poll_transfer(cx));
+        Poll::Ready(Ok(()))
+    }
 
-            impl<BUF> FusedStream for $RxStreamX<BUF>
-            where
-                BUF: Clone,
-            {
-                fn is_terminated(&self) -> bool {
-                    false
-                }
-            }
-        )+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
     }
 }
 
-rx_stream!(
-    RxStream1: (DMA1_CHANNEL5, RxDma1),
-    RxStream2: (DMA1_CHANNEL6, RxDma2),
-    RxStream3: (DMA1_CHANNEL3, RxDma3),
-);
+/// A [`Sink`] shared between tasks, such as a [`TxSink`] used for logging from several tasks.
+///
+/// Each [`send`](Self::send) holds a lock until its item has been transmitted completely.
+/// Interleaving is thus prevented at the granularity of items (frames), not bytes:
+/// frames from different tasks may still alternate, but never mix.
+///
+/// # Examples
+///
+/// ```
+/// static mut TX_SINK: Option<SharedTxSink<TxSink3<'static, [u8; 8]>>> = None;
+/// // In each task
+/// tx_sink.send(*b"01234567").await.unwrap();
+/// ```
+pub struct SharedTxSink<S>(Mutex<S>);
+
+impl<S> SharedTxSink<S> {
+    /// Creates a new [`SharedTxSink`] from the specified sink.
+    pub const fn new(sink: S) -> Self {
+        Self(Mutex::new(sink))
+    }
+
+    /// Locks the sink, transmits `item` and unlocks the sink again.
+    pub async fn send<Item>(&self, item: Item) -> Result<(), S::Error>
+    where
+        S: Sink<Item> + Unpin,
+    {
+        self.0.lock().await.send(item).await
+    }
+
+    /// Locks the sink for sending several items without interruption by other tasks.
+    pub async fn lock(&self) -> MutexGuard<'_, S> {
+        self.0.lock().await
+    }
+
+    /// Releases the shared sink.
+    pub fn into_inner(self) -> S {
+        self.0.into_inner()
+    }
+}
+
+/// A [`Sink`] dispatching each item to the first idle one of several sinks, such as [`TxSink`]s of different USARTs.
+///
+/// Sinks are tried round-robin, starting after the sink which has been sent the last item, so that no sink starves.
+/// Sinks of different types can be combined as trait objects.
+///
+/// # Examples
+///
+/// ```
+/// let mut fanout = TxFanout::new([
+///     &mut tx_sink1 as &mut (dyn Sink<[u8; 8], Error = Infallible> + Unpin),
+///     &mut tx_sink2,
+///     &mut tx_sink3,
+/// ]);
+/// fanout.send(*b"01234567").await.unwrap();
+/// ```
+pub struct TxFanout<'a, S: ?Sized, const N: usize> {
+    sinks: [&'a mut S; N],
+    busy: [bool; N],
+    next: usize,
+    ready: Option<usize>,
+}
+
+impl<'a, S: ?Sized, const N: usize> TxFanout<'a, S, N> {
+    /// Creates a new [`TxFanout`] from the specified sinks.
+    pub fn new(sinks: [&'a mut S; N]) -> Self {
+        Self {
+            sinks,
+            busy: [false; N],
+            next: 0,
+            ready: None,
+        }
+    }
+
+    /// Returns `true` if the sink at `index` had not flushed its last item when it was last polled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn is_busy(&self, index: usize) -> bool {
+        self.busy[index]
+    }
+
+    /// Releases the sinks.
+    pub fn release(self) -> [&'a mut S; N] {
+        self.sinks
+    }
+
+    /// Polls all sinks with `f`, which is either flushing or closing them.
+    fn poll_all<E>(
+        &mut self,
+        cx: &mut Context<'_>,
+        f: fn(Pin<&mut S>, &mut Context<'_>) -> Poll<Result<(), E>>,
+    ) -> Poll<Result<(), E>>
+    where
+        S: Unpin,
+    {
+        let mut pending = false;
+        for (sink, busy) in self.sinks.iter_mut().zip(self.busy.iter_mut()) {
+            match f(Pin::new(&mut **sink), cx) {
+                Poll::Ready(res) => {
+                    res?;
+                    *busy = false;
+                }
+                Poll::Pending => pending = true,
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+impl<Item, S, const N: usize> Sink<Item> for TxFanout<'_, S, N>
+where
+    S: Sink<Item> + Unpin + ?Sized,
+{
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.ready.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+        for i in 0..N {
+            let index = (this.next + i) % N;
+            if let Poll::Ready(res) = Pin::new(&mut *this.sinks[index]).poll_ready(cx) {
+                res?;
+                this.busy[index] = false;
+                this.ready = Some(index);
+                return Poll::Ready(Ok(()));
+            }
+        }
+        Poll::Pending
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let index = this
+            .ready
+            .take()
+            .expect("started sending before polled ready");
+        Pin::new(&mut *this.sinks[index]).start_send(item)?;
+        this.busy[index] = true;
+        this.next = (index + 1) % N;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_all(cx, S::poll_flush)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_all(cx, S::poll_close)
+    }
+}
+
+/// A [`Sink`] measuring the transmit throughput of another sink, such as a [`TxSink`].
+///
+/// The time is measured while items are in flight, from sending an item until the sink is ready again.
+/// Thus idle periods between items do not lower the throughput, while a stalling link, e.g. by CTS, does.
+///
+/// # Examples
+///
+/// ```
+/// let mut tx_sink = Metered::new(tx_sink, clocks.sysclk().into(), || {
+///     Instant::from_ticks(Ticks(DWT::get_cycle_count()))
+/// });
+/// tx_sink.send(*b"01234567").await.unwrap();
+/// let bps = tx_sink.throughput_bps();
+/// ```
+pub struct Metered<S, F> {
+    sink: S,
+    clock: Hz,
+    now: F,
+    bytes: u64,
+    busy: u64,
+    sending_since: Option<Instant>,
+}
+
+impl<S, F> Metered<S, F>
+where
+    F: FnMut() -> Instant,
+{
+    /// Creates a new [`Metered`] sink from the specified sink, reading the time from `now` which counts at `clock`.
+    pub fn new(sink: S, clock: Hz, now: F) -> Self {
+        Self {
+            sink,
+            clock,
+            now,
+            bytes: 0,
+            busy: 0,
+            sending_since: None,
+        }
+    }
+
+    /// Returns the throughput in bits per second since creation or the last [`reset`](Self::reset).
+    ///
+    /// Returns `0` if no item has been transmitted completely.
+    pub fn throughput_bps(&self) -> u32 {
+        if self.busy == 0 {
+            0
+        } else {
+            (self.bytes * 8 * u64::from(self.clock.0) / self.busy) as u32
+        }
+    }
+
+    /// Resets the measurement, e.g. for measuring consecutive windows.
+    pub fn reset(&mut self) {
+        self.bytes = 0;
+        self.busy = 0;
+    }
+
+    /// Releases the sink.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+
+    /// Adds the time since sending started, if sending, to the busy time.
+    fn stop_sending(&mut self) {
+        if let Some(since) = self.sending_since.take() {
+            self.busy += u64::from(((self.now)() - since).0);
+        }
+    }
+}
+
+impl<Item, S, F> Sink<Item> for Metered<S, F>
+where
+    Item: AsRef<[u8]>,
+    S: Sink<Item> + Unpin,
+    F: FnMut() -> Instant + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.sink).poll_ready(cx))?;
+        this.stop_sending();
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        this.bytes += item.as_ref().len() as u64;
+        if this.sending_since.is_none() {
+            this.sending_since = Some((this.now)());
+        }
+        Pin::new(&mut this.sink).start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.sink).poll_flush(cx))?;
+        this.stop_sending();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.sink).poll_close(cx))?;
+        this.stop_sending();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A [`Sink`] running a closure after each completed transfer.
+///
+/// This `struct` is created by the `on_complete` method on [`TxSink`].
+#[must_use = "sinks do nothing unless polled"]
+pub struct OnComplete<S, F> {
+    sink: S,
+    f: F,
+    sending: bool,
+}
+
+impl<S, F> OnComplete<S, F> {
+    /// Releases the sink.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+
+    /// Runs the closure if a transfer has been running.
+    fn complete(&mut self)
+    where
+        F: FnMut(),
+    {
+        if self.sending {
+            self.sending = false;
+            (self.f)();
+        }
+    }
+}
+
+impl<Item, S, F> Sink<Item> for OnComplete<S, F>
+where
+    S: Sink<Item> + Unpin,
+    F: FnMut() + Unpin,
+{
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.sink).poll_ready(cx))?;
+        this.complete();
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        Pin::new(&mut this.sink).start_send(item)?;
+        this.sending = true;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.sink).poll_flush(cx))?;
+        this.complete();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(Pin::new(&mut this.sink).poll_close(cx))?;
+        this.complete();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// An error returned by a [`HalfDuplexSession`] operation which is not allowed at this point of the protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfTurn;
+
+/// An error returned by [`HalfDuplexSession::send`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionError<E> {
+    /// A response has to be received before sending.
+    OutOfTurn,
+    /// The sink returned an error.
+    Sink(E),
+}
+
+impl<E> From<OutOfTurn> for SessionError<E> {
+    fn from(_: OutOfTurn) -> Self {
+        Self::OutOfTurn
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Turn {
+    Send,
+    Receive,
+}
+
+/// A request/response session enforcing strict turn-taking between sending and receiving.
+///
+/// The session starts with sending.
+/// After each [`send`](Self::send), which completes when the item has been transmitted, only a [`receive`](Self::receive) is allowed and vice versa.
+/// This prevents overlapping transmissions and receptions on a half-duplex bus.
+///
+/// # Examples
+///
+/// ```
+/// let mut session = HalfDuplexSession::new(tx_sink, rx_stream);
+/// session.send(request).await?;
+/// let response = session.receive().await?;
+/// ```
+pub struct HalfDuplexSession<Tx, Rx> {
+    tx: Tx,
+    rx: Rx,
+    turn: Turn,
+}
+
+impl<Tx, Rx> HalfDuplexSession<Tx, Rx> {
+    /// Creates a new [`HalfDuplexSession`] from the specified sink and stream.
+    pub fn new(tx: Tx, rx: Rx) -> Self {
+        Self {
+            tx,
+            rx,
+            turn: Turn::Send,
+        }
+    }
+
+    /// Transmits `item`, after which a response has to be received.
+    pub async fn send<Item>(&mut self, item: Item) -> Result<(), SessionError<Tx::Error>>
+    where
+        Tx: Sink<Item> + Unpin,
+    {
+        if self.turn != Turn::Send {
+            return Err(OutOfTurn.into());
+        }
+        self.tx.send(item).await.map_err(SessionError::Sink)?;
+        self.turn = Turn::Receive;
+        Ok(())
+    }
+
+    /// Receives the response to the last item sent.
+    pub async fn receive(&mut self) -> Result<Option<Rx::Item>, OutOfTurn>
+    where
+        Rx: Stream + Unpin,
+    {
+        if self.turn != Turn::Receive {
+            return Err(OutOfTurn);
+        }
+        let item = self.rx.next().await;
+        self.turn = Turn::Send;
+        Ok(item)
+    }
+
+    /// Releases the sink and stream.
+    pub fn release(self) -> (Tx, Rx) {
+        (self.tx, self.rx)
+    }
+}
+
+/// A [`Stream`]-based asynchronous abstraction over a DMA receiver.
+///
+/// # Examples
+///
+/// ```
+/// let mut tx_sink = TxSink3::new(tx_buf, tx.with_dma(channels.2));
+/// let mut rx_stream = RxStream3::new(rx_buf, rx.with_dma(channels.3));
+/// // Echoes USART3, by sending all items from the infinite RxStream
+/// tx_sink.send_all(&mut rx_stream).await?;
+/// unreachable!("rx_stream is empty");
+/// ```
+#[must_use = "streams do nothing unless polled"]
+pub struct RxStream<BUF, PAYLOAD>
+where
+    BUF: 'static,
+{
+    circ_buffer: CircBuffer<BUF, PAYLOAD>,
+    last_read_half: Half,
+    /// The position of the next byte to be read by `read_byte` in both halves.
+    #[cfg(feature = "usart-interrupts")]
+    byte_pos: usize,
+}
+
+/// A [`Stream`] yielding the halves of an [`RxStream`] together with the [`Half`] they have been received into.
+///
+/// This `struct` is created by the `halves` method on [`RxStream`].
+#[must_use = "streams do nothing unless polled"]
+pub struct Halves<'a, BUF, PAYLOAD>(&'a mut RxStream<BUF, PAYLOAD>)
+where
+    BUF: 'static;
+
+/// A [`Stream`] yielding the halves of an [`RxStream`], recovering from errors by restarting the DMA.
+///
+/// This `struct` is created by the `auto_recover` method on [`RxStream`].
+#[must_use = "streams do nothing unless polled"]
+pub struct AutoRecover<'a, BUF, PAYLOAD>
+where
+    BUF: 'static,
+{
+    rx_stream: &'a mut RxStream<BUF, PAYLOAD>,
+    recovered: u32,
+}
+
+impl<BUF, PAYLOAD> AutoRecover<'_, BUF, PAYLOAD> {
+    /// Returns the number of errors recovered from.
+    ///
+    /// Each recovery drops at least one half.
+    pub fn recovered(&self) -> u32 {
+        self.recovered
+    }
+}
+
+/// A [`Stream`] yielding the halves of an [`RxStream`], which drives an RTS pin based on the unconsumed bytes.
+///
+/// RTS is active low, i.e., driving it high tells the sender to pause.
+/// It is deasserted once the unconsumed bytes reach the high watermark and reasserted once they have fallen to the low watermark.
+/// As only completed halves are yielded, RTS is also reasserted whenever no completed half is pending.
+/// Otherwise, a sender overshooting the high watermark could leave more than `low` bytes in the half being filled, which would never complete.
+/// The bytes are checked on each poll and on each call to [`update`](Self::update), e.g. from a timer tick while the consumer is busy.
+///
+/// This `struct` is created by the `rts_flow_control` method on [`RxStream`].
+#[must_use = "streams do nothing unless polled"]
+pub struct RtsFlowControl<'a, BUF, PAYLOAD, P>
+where
+    BUF: 'static,
+{
+    rx_stream: &'a mut RxStream<BUF, PAYLOAD>,
+    rts: P,
+    high: usize,
+    low: usize,
+    paused: bool,
+}
+
+impl<BUF, PAYLOAD, P> RtsFlowControl<'_, BUF, PAYLOAD, P> {
+    /// Returns whether RTS is deasserted, i.e., whether the sender has been told to pause.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Releases the RTS pin.
+    pub fn release(self) -> P {
+        self.rts
+    }
+}
+
+/// Returns whether RTS should be deasserted for `unconsumed` bytes, given whether it is deasserted now.
+fn rts_paused(paused: bool, unconsumed: usize, half_len: usize, high: usize, low: usize) -> bool {
+    if unconsumed < half_len {
+        // Without a completed half pending, the consumer only makes progress with more bytes
+        false
+    } else if paused {
+        unconsumed > low
+    } else {
+        unconsumed >= high
+    }
+}
+
+/// A [`Future`]-based one-shot DMA receive of a fixed number of bytes.
+///
+/// Unlike [`RxStream`], the DMA stops once the buffer has been filled.
+/// The number of bytes received so far can be queried, e.g. for a download progress bar.
+/// Dropping a pending [`RxTransfer`] aborts the DMA.
+///
+/// # Examples
+///
+/// ```
+/// let mut transfer = RxTransfer3::new(image_buf, rx.with_dma(channels.3));
+/// let mut progress = transfer.progress(&mut timer, Ticks(7_200_000));
+/// while let Some(received) = progress.next().await {
+///     defmt::info!("received {:usize} bytes", received);
+/// }
+/// let (image_buf, rx) = transfer.await;
+/// ```
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct RxTransfer<BUF, PAYLOAD: TransferPayload> {
+    transfer: Option<Transfer<W, BUF, PAYLOAD>>,
+    len: usize,
+    /// Stops the DMA channel of the transfer.
+    stop: fn(),
+}
+
+impl<BUF, PAYLOAD: TransferPayload> Drop for RxTransfer<BUF, PAYLOAD> {
+    fn drop(&mut self) {
+        if self.transfer.is_some() {
+            (self.stop)();
+        }
+    }
+}
+
+/// A [`Stream`] yielding the number of bytes received by an [`RxTransfer`] at intervals.
+///
+/// The stream finishes after yielding the full length once the transfer has completed.
+///
+/// This `struct` is created by the `progress` method on [`RxTransfer`].
+#[must_use = "streams do nothing unless polled"]
+pub struct Progress<'a, 'b, BUF, PAYLOAD: TransferPayload, T: Instance> {
+    transfer: &'a mut RxTransfer<BUF, PAYLOAD>,
+    delay: Delay<'b, T>,
+    interval: Ticks,
+    finished: bool,
+}
+
+/// A [`Stream`] yielding Modbus RTU frames received by an [`RxStream`].
+///
+/// A frame ends after a silent interval of 3.5 character times.
+/// The received bytes are collected as soon as the DMA has written them, independently of the halves of the [`RxStream`].
+/// While a frame is being received, the DMA position is sampled every half character time on the timer.
+/// Each time it has changed, the silent interval is restarted from that sample, so it is never detected early.
+/// As a byte is detected up to half a character time after the DMA has written it, a frame ends between 3.5 and 4 character times after its last byte, plus the latency of waking the task for each sample.
+/// The first byte of the next frame is only written by the DMA 4.5 character times after the last byte, so frames separated by exactly 3.5 character times are not merged.
+/// The STM32F1 has no trigger connection from the USARTs to the timers, which would allow retriggering the timer in hardware.
+///
+/// Frames are accumulated in a buffer of `N` bytes.
+/// Frames not fitting into this buffer are discarded and reported as [`framing::Error::TooLong`].
+/// Bytes which have not been collected before the DMA has wrapped around are lost, so the [`RxStream`] buffers should hold a full frame.
+///
+/// This `struct` is created by the `modbus_framed` method on [`RxStream`], which requires the `usart-interrupts` feature.
+#[cfg(feature = "usart-interrupts")]
+#[must_use = "streams do nothing unless polled"]
+pub struct ModbusFramed<'a, 'b, BUF, PAYLOAD, T: Instance, const N: usize>
+where
+    BUF: 'static,
+{
+    rx_stream: &'a mut RxStream<BUF, PAYLOAD>,
+    delay: Delay<'b, T>,
+    /// Half a character time.
+    tick: Ticks,
+    /// The number of ticks since the DMA position has last changed.
+    silent_ticks: u8,
+    frame: Vec<u8, N>,
+    too_long: bool,
+}
+
+/// The silent interval of 3.5 character times ending a Modbus RTU frame, in half character times.
+#[cfg(feature = "usart-interrupts")]
+const MODBUS_GAP_TICKS: u8 = 7;
+
+/// An error of a [`BaudMismatchDetector`].
+#[derive(Debug)]
+pub enum BaudError {
+    /// The DMA returned an error.
+    Dma(dma::Error),
+    /// Framing errors have exceeded the threshold, which likely results from a wrong baud rate.
+    ///
+    /// The half received last is discarded.
+    LikelyBaudMismatch,
+}
+
+/// A [`Stream`] yielding the halves of an [`RxStream`], which detects a likely baud rate mismatch from framing errors.
+///
+/// The USART's framing error flag is checked for each half.
+/// The halves are counted in windows of `window` halves.
+/// If `threshold` halves of a window have had framing errors, [`BaudError::LikelyBaudMismatch`] is yielded and a new window is started.
+/// Occasional framing errors due to noise don't reach the threshold.
+///
+/// This `struct` is created by the `detect_baud_mismatch` method on [`RxStream`].
+#[must_use = "streams do nothing unless polled"]
+pub struct BaudMismatchDetector<'a, BUF, PAYLOAD>
+where
+    BUF: 'static,
+{
+    rx_stream: &'a mut RxStream<BUF, PAYLOAD>,
+    window: u32,
+    threshold: u32,
+    halves: u32,
+    errors: u32,
+}
+
+impl<BUF, PAYLOAD> BaudMismatchDetector<'_, BUF, PAYLOAD> {
+    /// Counts a received half, returning whether the threshold has been reached in the current window.
+    fn count(&mut self, framing_error: bool) -> bool {
+        self.halves += 1;
+        if framing_error {
+            self.errors += 1;
+        }
+
+        let mismatch = self.errors >= self.threshold;
+        if mismatch || self.halves >= self.window {
+            self.halves = 0;
+            self.errors = 0;
+        }
+        mismatch
+    }
+}
+
+macro_rules! rx_stream {
+    ($(
+        $RxStreamX:ident, $RxTransferX:ident: ($USARTX:ident, $INT:ident, $chX:ident, [$htifX:ident, $chtifX:ident, $tcifX:ident, $ctcifX:ident], $rxdma:ty),
+    )+) => {
+        $(
+            /// A type shorthand for specifying different DMA channels easily.
+            pub type $RxStreamX<BUF> = RxStream<BUF, $rxdma>;
+
+            /// A type shorthand for specifying different DMA channels easily.
+            pub type $RxTransferX<BUF> = RxTransfer<BUF, $rxdma>;
+
+            impl<BUF> $RxTransferX<BUF>
+            where
+                BUF: StaticWriteBuffer<Word = u8>,
+            {
+                /// Starts receiving into the specified buffer with the DMA transmitter.
+                pub fn new(mut buf: BUF, mut rx: $rxdma) -> Self {
+                    // Safety: Only the length is used
+                    let (_, len) = unsafe { buf.static_write_buffer() };
+                    rx.channel.listen(Event::TransferComplete);
+                    Self {
+                        transfer: Some(rx.read(buf)),
+                        len,
+                        // Safety: This is an atomic access to the channel owned by the transfer
+                        stop: || unsafe { (*DMA1::ptr()).$chX.cr.modify(|_, w| w.en().clear_bit()) },
+                    }
+                }
+            }
+
+            impl<BUF> $RxTransferX<BUF> {
+                /// Returns the number of bytes received so far.
+                pub fn received(&self) -> usize {
+                    if self.transfer.is_none() {
+                        return self.len;
+                    }
+                    // Safety: This is an atomic read of the remaining transfers of our channel
+                    let remaining = unsafe { (*DMA1::ptr()).$chX.ndtr.read().ndt().bits() };
+                    self.len - usize::from(remaining)
+                }
+
+                /// Creates a [`Stream`] yielding the number of bytes received every `interval` input clock cycles of `timer`.
+                ///
+                /// See [`Progress`].
+                pub fn progress<'b, T, D>(&mut self, timer: &'b mut AsyncTimer<T>, interval: D) -> Progress<'_, 'b, BUF, $rxdma, T>
+                where
+                    T: Instance,
+                    D: Into<Ticks>,
+                {
+                    let interval = interval.into();
+                    Progress {
+                        transfer: self,
+                        delay: timer.delay(interval),
+                        interval,
+                        finished: false,
+                    }
+                }
+
+                fn is_done(&self) -> bool {
+                    self.transfer.as_ref().map_or(true, |transfer| transfer.is_done())
+                }
+            }
+
+            impl<BUF> Future for $RxTransferX<BUF>
+            where
+                BUF: Unpin,
+            {
+                type Output = (BUF, $rxdma);
+
+                fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                    let transfer = match self.transfer.as_ref() {
+                        Some(transfer) => transfer,
+                        // Polled after completion, see FusedFuture
+                        None => return Poll::Pending,
+                    };
+                    if transfer.is_done() {
+                        Poll::Ready(self.transfer.take().unwrap().wait())
+                    } else {
+                        waker_interrupt!($INT, cx.waker());
+                        Poll::Pending
+                    }
+                }
+            }
+
+            impl<BUF> FusedFuture for $RxTransferX<BUF>
+            where
+                BUF: Unpin,
+            {
+                fn is_terminated(&self) -> bool {
+                    self.transfer.is_none()
+                }
+            }
+
+            impl<BUF, T> Stream for Progress<'_, '_, BUF, $rxdma, T>
+            where
+                T: Instance,
+            {
+                type Item = usize;
+
+                fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                    let this = self.get_mut();
+                    if this.finished {
+                        return Poll::Ready(None);
+                    }
+                    if this.transfer.is_done() {
+                        this.finished = true;
+                        return Poll::Ready(Some(this.transfer.len));
+                    }
+                    if Pin::new(&mut this.delay).poll(cx).is_ready() {
+                        this.delay.restart(this.interval);
+                        return Poll::Ready(Some(this.transfer.received()));
+                    }
+                    // Also wake on completion
+                    waker_interrupt!($INT, cx.waker());
+                    Poll::Pending
+                }
+
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    if self.finished {
+                        (0, Some(0))
+                    } else if self.transfer.received() == self.transfer.len {
+                        // Only the full length remains
+                        (1, Some(1))
+                    } else {
+                        // The full length and any number of intervals remain
+                        (1, None)
+                    }
+                }
+            }
+
+            impl<BUF> $RxStreamX<BUF>
+            where
+                &'static mut [BUF; 2]: StaticWriteBuffer<Word = u8>,
+            {
+                /// Creates a new [`RxStream`] from the specified buffers and DMA transmitter.
+                pub fn new(buf: &'static mut [BUF; 2], mut rx: $rxdma) -> Self
+                where
+                    BUF: AsMutSlice<Element = u8>,
+                {
+                    rx.channel.listen(Event::HalfTransfer);
+                    rx.channel.listen(Event::TransferComplete);
+                    Self {
+                        circ_buffer: rx.circ_read(buf),
+                        last_read_half: Half::Second,
+                        #[cfg(feature = "usart-interrupts")]
+                        byte_pos: 0,
+                    }
+                }
+
+                /// Releases the buffers and DMA transmitter.
+                pub fn release(self) -> (&'static mut [BUF; 2], $rxdma) {
+                    self.circ_buffer.stop()
+                }
+
+                /// Restarts the DMA with buffers of a different length, returning the old buffers.
+                ///
+                /// This is useful for growing the buffers after negotiating a protocol.
+                /// Bytes received into the old buffers which have not been yielded yet are lost.
+                pub fn resize<NEWBUF>(self, buf: &'static mut [NEWBUF; 2]) -> ($RxStreamX<NEWBUF>, &'static mut [BUF; 2])
+                where
+                    NEWBUF: AsMutSlice<Element = u8>,
+                    &'static mut [NEWBUF; 2]: StaticWriteBuffer<Word = u8>,
+                {
+                    let (old, rx) = self.release();
+                    ($RxStreamX::new(buf, rx), old)
+                }
+
+                /// Restarts the DMA from the first half, discarding any received data.
+                ///
+                /// This recovers the stream after an error, such as [`dma::Error::Overrun`].
+                pub fn recover(&mut self)
+                where
+                    BUF: AsMutSlice<Element = u8>,
+                {
+                    // Safety: circ_buffer is overwritten before being used again.
+                    // It has no drop glue, so a panic in between can't result in a double drop.
+                    unsafe {
+                        let (buf, rx) = ptr::read(&self.circ_buffer).stop();
+                        ptr::write(&mut self.circ_buffer, rx.circ_read(buf));
+                    }
+                    self.last_read_half = Half::Second;
+                    #[cfg(feature = "usart-interrupts")]
+                    {
+                        self.byte_pos = 0;
+                    }
+                }
+
+                /// Creates a [`Stream`] yielding the received halves, which transparently recovers from errors.
+                ///
+                /// This is useful for gapless logging, where dropping data on a transient overrun is preferable to handling errors.
+                /// The number of recoveries can be queried from the returned [`AutoRecover`].
+                pub fn auto_recover(&mut self) -> AutoRecover<'_, BUF, $rxdma> {
+                    AutoRecover {
+                        rx_stream: self,
+                        recovered: 0,
+                    }
+                }
+
+                /// Waits for the half currently being filled or an idle line, then releases the buffers and DMA transmitter.
+                ///
+                /// Unlike [`release`](Self::release), this does not tear the half the DMA is writing to.
+                /// Returns the halves which have not been yielded yet:
+                /// an already completed half, if any, followed by the half that was being filled and the number of bytes received into it.
+                /// The DMA is stopped before this number is read, so it covers every byte written into the returned half.
+                ///
+                /// The idle line is detected by the USART's idle line interrupt.
+                /// An idle flag left over from before waiting is only trusted if no byte has been received since this has started waiting.
+                ///
+                /// This requires the `usart-interrupts` feature.
+                #[cfg(feature = "usart-interrupts")]
+                pub async fn release_graceful(
+                    mut self,
+                ) -> (Result<(Option<BUF>, BUF, usize), dma::Error>, &'static mut [BUF; 2], $rxdma)
+                where
+                    BUF: Clone,
+                {
+                    let ready = match self.next().now_or_never().flatten().transpose() {
+                        Ok(ready) => ready,
+                        Err(err) => {
+                            let (buf, rx) = self.release();
+                            return (Err(err), buf, rx);
+                        }
+                    };
+
+                    let mut armed = false;
+                    let last = future::poll_fn(|cx| {
+                        if let Poll::Ready(res) = self.poll_next_with(cx, BUF::clone) {
+                            return Poll::Ready(res.map(Some));
+                        }
+                        // Safety: This is an atomic read of the status register.
+                        // After it, the DMA reading the next byte clears the idle flag.
+                        let idle = unsafe { (*$USARTX::ptr()).sr.read().idle().bit_is_set() };
+                        if armed && idle {
+                            return Poll::Ready(Ok(None));
+                        }
+                        armed = true;
+                        // A flag left over from before raises the interrupt immediately, checking it again
+                        usart_wakers::$USARTX::RX_WAKER.register(cx.waker());
+                        usart_wakers::$USARTX::modify_masked(|usart| usart.cr1.modify(|_, w| w.idleie().set_bit()));
+                        Poll::Pending
+                    })
+                    .await;
+                    Self::unlisten_idle();
+
+                    // Stop the DMA before reading its position, so no byte is written after it
+                    // Safety: The channel is owned by the circular buffer, which is released right after
+                    unsafe { (*DMA1::ptr()).$chX.cr.modify(|_, w| w.en().clear_bit()) };
+                    let half = Self::writing_half();
+                    let len = self.dma_position();
+                    let (buf, rx) = self.release();
+                    let halves = last.map(|last| match last {
+                        Some(last) => (ready, last, mem::size_of::<BUF>()),
+                        None => {
+                            let filling = match half {
+                                Half::First => &buf[0],
+                                Half::Second => &buf[1],
+                            };
+                            (ready, filling.clone(), len)
+                        }
+                    });
+                    (halves, buf, rx)
+                }
+            }
+
+            impl<BUF> $RxStreamX<BUF> {
+                /// Polls for the next half, processing it in place with `f`.
+                ///
+                /// Unlike polling the [`Stream`], this does not clone the half, which is useful for large buffers.
+                ///
+                /// If the DMA has wrapped around into the half while it was being processed, [`dma::Error::Overrun`] is returned instead of the torn result.
+                pub fn poll_next_with<F, T>(&mut self, cx: &mut Context<'_>, mut f: F) -> Poll<Result<T, dma::Error>>
+                where
+                    F: FnMut(&BUF) -> T,
+                {
+                    let last_read_half = self.last_read_half;
+                    let res = self.circ_buffer.peek(|buf, half| {
+                        if half == last_read_half {
+                            None
+                        } else {
+                            Some((f(buf), half))
+                        }
+                    });
+
+                    match res {
+                        Ok(Some((item, half))) => {
+                            record_poll!(RX_STREAM, Ready);
+                            self.last_read_half = half;
+                            if Self::writing_half() == half {
+                                Poll::Ready(Err(dma::Error::Overrun))
+                            } else {
+                                Poll::Ready(Ok(item))
+                            }
+                        }
+                        Ok(None) => {
+                            record_poll!(RX_STREAM, Pending, $INT);
+                            waker_interrupt!($INT, cx.waker());
+                            Poll::Pending
+                        }
+                        Err(err) => {
+                            record_poll!(RX_STREAM, Ready);
+                            Poll::Ready(Err(err))
+                        }
+                    }
+                }
+
+                /// Returns the number of bytes the DMA has not yet written in the current cycle through both halves.
+                fn remaining() -> usize {
+                    // Safety: This is an atomic read of the remaining transfers of our channel
+                    let remaining = unsafe { (*DMA1::ptr()).$chX.ndtr.read().ndt().bits() };
+                    // With u8 words, each transfer is one byte
+                    usize::from(remaining)
+                }
+
+                /// Returns the half the DMA is currently writing to.
+                fn writing_half() -> Half {
+                    if Self::remaining() > mem::size_of::<BUF>() {
+                        Half::First
+                    } else {
+                        Half::Second
+                    }
+                }
+
+                /// Returns the number of bytes the DMA has written into the half it is currently writing to.
+                ///
+                /// This only reads the transfer counter of the DMA channel.
+                /// Sampling it is useful for sizing buffers, as a position close to the end of the half that has not been yielded yet indicates an imminent overrun.
+                pub fn dma_position(&self) -> usize {
+                    let len = mem::size_of::<BUF>();
+                    (2 * len - Self::remaining()) % len
+                }
+
+                /// Returns the number of bytes the DMA has written since the end of the last yielded half.
+                ///
+                /// Once this reaches the size of both halves, the DMA overruns the data not yet yielded.
+                pub fn unconsumed(&self) -> usize {
+                    let len = 2 * mem::size_of::<BUF>();
+                    let written = len - Self::remaining();
+                    let read = match self.last_read_half {
+                        Half::First => len / 2,
+                        Half::Second => 0,
+                    };
+                    (written + len - read) % len
+                }
+
+                /// Creates a [`Stream`] yielding the received halves, which drives `rts` for hardware flow control.
+                ///
+                /// `rts` is deasserted once [`unconsumed`](Self::unconsumed) reaches `high` and reasserted once it has fallen to `low` or no completed half is pending.
+                /// See [`RtsFlowControl`].
+                ///
+                /// # Panics
+                ///
+                /// Panics if `low` is not less than `high`.
+                ///
+                /// # Examples
+                ///
+                /// ```
+                /// let mut rx = rx_stream.rts_flow_control(rts, 48, 16);
+                /// while let Some(buf) = rx.next().await {
+                ///     process(buf?).await;
+                /// }
+                /// ```
+                pub fn rts_flow_control<P>(&mut self, mut rts: P, high: usize, low: usize) -> RtsFlowControl<'_, BUF, $rxdma, P>
+                where
+                    P: OutputPin<Error = Infallible>,
+                {
+                    assert!(low < high, "low watermark is not less than high watermark");
+                    rts.set_low().unwrap();
+                    let mut flow_control = RtsFlowControl {
+                        rx_stream: self,
+                        rts,
+                        high,
+                        low,
+                        paused: false,
+                    };
+                    flow_control.update();
+                    flow_control
+                }
+
+                /// Creates a [`Future`] that resolves to the result of processing the next half in place with `f`.
+                ///
+                /// See [`poll_next_with`](Self::poll_next_with).
+                pub fn next_with<'a, F, T>(&'a mut self, mut f: F) -> impl Future<Output = Result<T, dma::Error>> + 'a
+                where
+                    F: FnMut(&BUF) -> T + 'a,
+                    T: 'a,
+                {
+                    future::poll_fn(move |cx| self.poll_next_with(cx, &mut f))
+                }
+
+                /// Waits for the DMA to have filled the first half, clearing the half transfer event.
+                ///
+                /// This allows custom double-buffering schemes below the [`Stream`] abstraction.
+                /// Mixing this with polling the [`Stream`] may skip halves, as both consume the same events.
+                pub fn wait_half_transfer(&mut self) -> impl Future<Output = ()> + '_ {
+                    future::poll_fn(move |cx| {
+                        // Safety: These are atomic accesses to the flags of our channel
+                        let dma1 = unsafe { &*DMA1::ptr() };
+                        if dma1.isr.read().$htifX().bit_is_set() {
+                            dma1.ifcr.write(|w| w.$chtifX().set_bit());
+                            Poll::Ready(())
+                        } else {
+                            waker_interrupt!($INT, cx.waker());
+                            Poll::Pending
+                        }
+                    })
+                }
+
+                /// Waits for the DMA to have filled the second half, clearing the transfer complete event.
+                ///
+                /// See [`wait_half_transfer`](Self::wait_half_transfer).
+                pub fn wait_transfer_complete(&mut self) -> impl Future<Output = ()> + '_ {
+                    future::poll_fn(move |cx| {
+                        // Safety: These are atomic accesses to the flags of our channel
+                        let dma1 = unsafe { &*DMA1::ptr() };
+                        if dma1.isr.read().$tcifX().bit_is_set() {
+                            dma1.ifcr.write(|w| w.$ctcifX().set_bit());
+                            Poll::Ready(())
+                        } else {
+                            waker_interrupt!($INT, cx.waker());
+                            Poll::Pending
+                        }
+                    })
+                }
+
+                /// Creates a [`Future`] that resolves to the next received byte as soon as it is available.
+                ///
+                /// Unlike polling the [`Stream`], this does not wait for a half to be filled, which is useful for interactive consoles.
+                /// The task is woken by the USART's idle line interrupt, which follows each burst of bytes.
+                /// If the idle flag of the previous burst has not been cleared yet, the task is woken by the next received byte instead.
+                ///
+                /// Bytes are read at an independent position, so mixing this with polling the [`Stream`] does not lose bytes, but yields them twice.
+                /// Bytes which have not been read before the DMA has wrapped around are skipped.
+                ///
+                /// This requires the `usart-interrupts` feature.
+                #[cfg(feature = "usart-interrupts")]
+                pub fn read_byte(&mut self) -> impl Future<Output = u8> + '_ {
+                    future::poll_fn(move |cx| {
+                        let byte = match self.try_read_byte() {
+                            Some(byte) => byte,
+                            None => {
+                                Self::listen_idle(cx);
+                                // A byte may have been received while arming
+                                match self.try_read_byte() {
+                                    Some(byte) => byte,
+                                    None => return Poll::Pending,
+                                }
+                            }
+                        };
+                        Self::unlisten_idle();
+                        Poll::Ready(byte)
+                    })
+                }
+
+                /// Registers the task to be woken by the USART's next idle line interrupt.
+                ///
+                /// The idle flag is only cleared by reading SR followed by DR.
+                /// DR is not read here, as this would race with the DMA reading the received byte.
+                /// Instead, the DMA clears the flag when reading the next byte after SR has been read, e.g. here.
+                /// Until then, the stale flag would raise the interrupt immediately, so the task is woken by the next received byte instead.
+                #[cfg(feature = "usart-interrupts")]
+                fn listen_idle(cx: &mut Context<'_>) {
+                    usart_wakers::$USARTX::RX_WAKER.register(cx.waker());
+                    usart_wakers::$USARTX::modify_masked(|usart| {
+                        if usart.sr.read().idle().bit_is_set() {
+                            usart.cr1.modify(|_, w| w.rxneie().set_bit());
+                        } else {
+                            usart.cr1.modify(|_, w| w.idleie().set_bit());
+                        }
+                    });
+                }
+
+                /// Disables the interrupts enabled by [`listen_idle`](Self::listen_idle), if the USART has not raised them yet.
+                #[cfg(feature = "usart-interrupts")]
+                fn unlisten_idle() {
+                    // Safety: This is an atomic read of the control register
+                    let cr1 = unsafe { (*$USARTX::ptr()).cr1.read() };
+                    if cr1.idleie().bit_is_set() || cr1.rxneie().bit_is_set() {
+                        usart_wakers::$USARTX::modify_masked(|usart| {
+                            usart.cr1.modify(|_, w| w.idleie().clear_bit().rxneie().clear_bit())
+                        });
+                    }
+                }
+
+                /// Creates a [`Stream`] yielding Modbus RTU frames, which are delimited by silent intervals of 3.5 character times.
+                ///
+                /// `char_time` is the duration of one character in input clock cycles of `timer`, e.g. 11 bit times.
+                /// See [`ModbusFramed`].
+                ///
+                /// This requires the `usart-interrupts` feature.
+                ///
+                /// # Examples
+                ///
+                /// ```
+                /// // 11 bits per character at 19200 baud
+                /// let char_time = Ticks::from_hz(Hz(19_200), CountDownTimer::<TIM2>::clock(&clocks)).0 * 11;
+                /// let mut frames = rx_stream.modbus_framed::<_, _, 256>(&mut timer, Ticks(char_time));
+                /// while let Some(frame) = frames.next().await {
+                ///     handle(frame?);
+                /// }
+                /// ```
+                #[cfg(feature = "usart-interrupts")]
+                pub fn modbus_framed<'b, T, D, const N: usize>(&mut self, timer: &'b mut AsyncTimer<T>, char_time: D) -> ModbusFramed<'_, 'b, BUF, $rxdma, T, N>
+                where
+                    T: Instance,
+                    D: Into<Ticks>,
+                {
+                    let tick = Ticks(char_time.into().0 / 2);
+                    let mut delay = timer.delay(tick);
+                    // The delay is started by the first byte
+                    delay.stop();
+                    ModbusFramed {
+                        rx_stream: self,
+                        delay,
+                        tick,
+                        silent_ticks: 0,
+                        frame: Vec::new(),
+                        too_long: false,
+                    }
+                }
+
+                /// Reads the byte at `byte_pos`, if the DMA has written it.
+                #[cfg(feature = "usart-interrupts")]
+                fn try_read_byte(&mut self) -> Option<u8> {
+                    if !self.has_unread_byte() {
+                        return None;
+                    }
+
+                    // Safety: The memory address of our channel points to the buffers, which are owned by the DMA.
+                    // The byte has already been written and is only read.
+                    let byte = unsafe {
+                        let buf = (*DMA1::ptr()).$chX.mar.read().bits() as *const u8;
+                        ptr::read_volatile(buf.add(self.byte_pos))
+                    };
+                    self.byte_pos = (self.byte_pos + 1) % (2 * mem::size_of::<BUF>());
+                    Some(byte)
+                }
+
+                /// Returns whether the DMA has written the byte at `byte_pos`.
+                #[cfg(feature = "usart-interrupts")]
+                fn has_unread_byte(&self) -> bool {
+                    let len = 2 * mem::size_of::<BUF>();
+                    let written = (len - Self::remaining()) % len;
+                    written != self.byte_pos
+                }
+
+                /// Returns whether the USART has detected a framing error since the last call.
+                fn framing_error() -> bool {
+                    // Safety: This is an atomic read of the status register.
+                    // The flag is cleared by the DMA reading the next byte from the data register.
+                    unsafe { (*$USARTX::ptr()).sr.read().fe().bit_is_set() }
+                }
+
+                /// Creates a [`Stream`] yielding the received halves, which reports a likely baud rate mismatch.
+                ///
+                /// See [`BaudMismatchDetector`].
+                ///
+                /// # Panics
+                ///
+                /// Panics if `threshold` is zero or exceeds `window`.
+                ///
+                /// # Examples
+                ///
+                /// ```
+                /// // Report if 4 of 16 halves have framing errors
+                /// let mut rx = rx_stream.detect_baud_mismatch(16, 4);
+                /// while let Some(res) = rx.next().await {
+                ///     match res {
+                ///         Ok(buf) => process(buf),
+                ///         Err(BaudError::LikelyBaudMismatch) => defmt::warn!("check the baud rate"),
+                ///         Err(BaudError::Dma(err)) => return Err(err),
+                ///     }
+                /// }
+                /// ```
+                pub fn detect_baud_mismatch(&mut self, window: u32, threshold: u32) -> BaudMismatchDetector<'_, BUF, $rxdma> {
+                    assert!(0 < threshold && threshold <= window, "threshold is not within the window");
+                    // Reading the flag lets the DMA clear a stale one with the next byte
+                    Self::framing_error();
+                    BaudMismatchDetector {
+                        rx_stream: self,
+                        window,
+                        threshold,
+                        halves: 0,
+                        errors: 0,
+                    }
+                }
+
+                /// Creates a [`Stream`] yielding the received halves together with the [`Half`] they have been received into.
+                ///
+                /// This is useful for routing alternating halves to different destinations.
+                pub fn halves(&mut self) -> Halves<'_, BUF, $rxdma> {
+                    Halves(self)
+                }
+            }
+
+            impl<BUF> Stream for Halves<'_, BUF, $rxdma>
+            where
+                BUF: Clone,
+            {
+                type Item = Result<(BUF, Half), dma::Error>;
+
+                fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                    let rx_stream = &mut *self.get_mut().0;
+                    rx_stream
+                        .poll_next_with(cx, BUF::clone)
+                        .map(|res| Some(res.map(|buf| (buf, rx_stream.last_read_half))))
+                }
+
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    (usize::MAX, None)
+                }
+            }
+
+            impl<BUF> FusedStream for Halves<'_, BUF, $rxdma>
+            where
+                BUF: Clone,
+            {
+                fn is_terminated(&self) -> bool {
+                    false
+                }
+            }
+
+            impl<BUF> Stream for $RxStreamX<BUF>
+            where
+                BUF: Clone,
+            {
+                type Item = Result<BUF, dma::Error>;
+
+                fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                    self.get_mut().poll_next_with(cx, BUF::clone).map(Some)
+                }
+
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    (usize::MAX, None)
+                }
+            }
+
+
+            impl<BUF> FusedStream for $RxStreamX<BUF>
+            where
+                BUF: Clone,
+            {
+                fn is_terminated(&self) -> bool {
+                    false
+                }
+            }
+
+            impl<BUF, P> RtsFlowControl<'_, BUF, $rxdma, P>
+            where
+                P: OutputPin<Error = Infallible>,
+            {
+                /// Deasserts or reasserts RTS according to the unconsumed bytes.
+                pub fn update(&mut self) {
+                    let unconsumed = self.rx_stream.unconsumed();
+                    let paused = rts_paused(self.paused, unconsumed, mem::size_of::<BUF>(), self.high, self.low);
+                    if paused && !self.paused {
+                        self.rts.set_high().unwrap();
+                    } else if !paused && self.paused {
+                        self.rts.set_low().unwrap();
+                    }
+                    self.paused = paused;
+                }
+            }
+
+            impl<BUF, P> Stream for RtsFlowControl<'_, BUF, $rxdma, P>
+            where
+                BUF: Clone,
+                P: OutputPin<Error = Infallible> + Unpin,
+            {
+                type Item = Result<BUF, dma::Error>;
+
+                fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                    let this = self.get_mut();
+                    let poll = this.rx_stream.poll_next_with(cx, BUF::clone);
+                    this.update();
+                    poll.map(Some)
+                }
+
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    (usize::MAX, None)
+                }
+            }
+
+            impl<BUF> Stream for BaudMismatchDetector<'_, BUF, $rxdma>
+            where
+                BUF: Clone,
+            {
+                type Item = Result<BUF, BaudError>;
+
+                fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                    let this = self.get_mut();
+                    let res = ready!(this.rx_stream.poll_next_with(cx, BUF::clone));
+                    let res = match res {
+                        Ok(_) if this.count($RxStreamX::<BUF>::framing_error()) => Err(BaudError::LikelyBaudMismatch),
+                        Ok(buf) => Ok(buf),
+                        Err(err) => Err(BaudError::Dma(err)),
+                    };
+                    Poll::Ready(Some(res))
+                }
+
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    (usize::MAX, None)
+                }
+            }
+
+            #[cfg(feature = "usart-interrupts")]
+            impl<BUF, T, const N: usize> Stream for ModbusFramed<'_, '_, BUF, $rxdma, T, N>
+            where
+                T: Instance,
+            {
+                type Item = Result<Vec<u8, N>, framing::Error<Infallible>>;
+
+                fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                    let this = self.get_mut();
+                    loop {
+                        let mut received = false;
+                        while let Some(byte) = this.rx_stream.try_read_byte() {
+                            received = true;
+                            if this.frame.push(byte).is_err() {
+                                this.too_long = true;
+                            }
+                        }
+                        if received {
+                            $RxStreamX::<BUF>::unlisten_idle();
+                            // The silent interval starts at this sample.
+                            // After a long idle period, this also discards the update event of the stopped delay.
+                            this.silent_ticks = 0;
+                            this.delay.restart(this.tick);
+                        }
+
+                        if this.frame.is_empty() && !this.too_long {
+                            $RxStreamX::<BUF>::listen_idle(cx);
+                            // A byte may have been received while arming
+                            if this.rx_stream.has_unread_byte() {
+                                continue;
+                            }
+                            return Poll::Pending;
+                        }
+
+                        if this.silent_ticks == MODBUS_GAP_TICKS {
+                            // Keep the delay from raising update events while the line is idle
+                            this.delay.stop();
+                            this.silent_ticks = 0;
+                            let frame = mem::take(&mut this.frame);
+                            let res = if mem::take(&mut this.too_long) {
+                                Err(framing::Error::TooLong)
+                            } else {
+                                Ok(frame)
+                            };
+                            return Poll::Ready(Some(res));
+                        }
+
+                        crate::timer::unwrap_infallible(ready!(Pin::new(&mut this.delay).poll(cx)));
+                        this.silent_ticks += 1;
+                        if this.silent_ticks < MODBUS_GAP_TICKS {
+                            this.delay.restart(this.tick);
+                        }
+                    }
+                }
+
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    (usize::MAX, None)
+                }
+            }
+
+            impl<BUF> Stream for AutoRecover<'_, BUF, $rxdma>
+            where
+                BUF: AsMutSlice<Element = u8> + Clone,
+                &'static mut [BUF; 2]: StaticWriteBuffer<Word = u8>,
+            {
+                type Item = BUF;
+
+                fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                    let this = self.get_mut();
+                    loop {
+                        match ready!(this.rx_stream.poll_next_with(cx, BUF::clone)) {
+                            Ok(buf) => return Poll::Ready(Some(buf)),
+                            Err(_) => {
+                                this.rx_stream.recover();
+                                this.recovered += 1;
+                            }
+                        }
+                    }
+                }
+
+                fn size_hint(&self) -> (usize, Option<usize>) {
+                    (usize::MAX, None)
+                }
+            }
+
+            impl<BUF> FusedStream for AutoRecover<'_, BUF, $rxdma>
+            where
+                BUF: AsMutSlice<Element = u8> + Clone,
+                &'static mut [BUF; 2]: StaticWriteBuffer<Word = u8>,
+            {
+                fn is_terminated(&self) -> bool {
+                    false
+                }
+            }
+        )+
+    }
+}
+
+rx_stream!(
+    RxStream1, RxTransfer1: (USART1, DMA1_CHANNEL5, ch5, [htif5, chtif5, tcif5, ctcif5], RxDma1),
+    RxStream2, RxTransfer2: (USART2, DMA1_CHANNEL6, ch6, [htif6, chtif6, tcif6, ctcif6], RxDma2),
+    RxStream3, RxTransfer3: (USART3, DMA1_CHANNEL3, ch3, [htif3, chtif3, tcif3, ctcif3], RxDma3),
+);
+
+/// Merges several streams, such as [`RxStream`]s of different USARTs, into one stream tagged with the index of the source.
+///
+/// The streams are polled round-robin, starting after the stream which yielded the last item, so that no stream starves.
+/// Streams of different types can be combined as trait objects.
+/// The merged stream finishes once all streams have finished.
+///
+/// # Examples
+///
+/// ```
+/// let mut merged = serial::merge_tagged([
+///     &mut rx_stream1 as &mut (dyn Stream<Item = Result<[u8; 8], dma::Error>> + Unpin),
+///     &mut rx_stream2,
+/// ]);
+/// while let Some((port, half)) = merged.next().await {
+///     route(port, half?);
+/// }
+/// ```
+pub fn merge_tagged<S: ?Sized, const N: usize>(streams: [&mut S; N]) -> MergeTagged<'_, S, N> {
+    MergeTagged {
+        streams,
+        terminated: [false; N],
+        next: 0,
+    }
+}
+
+/// Stream for the [`merge_tagged`] function.
+#[must_use = "streams do nothing unless polled"]
+pub struct MergeTagged<'a, S: ?Sized, const N: usize> {
+    streams: [&'a mut S; N],
+    terminated: [bool; N],
+    next: usize,
+}
+
+impl<'a, S: ?Sized, const N: usize> MergeTagged<'a, S, N> {
+    /// Releases the streams.
+    pub fn release(self) -> [&'a mut S; N] {
+        self.streams
+    }
+}
+
+impl<S, const N: usize> Stream for MergeTagged<'_, S, N>
+where
+    S: Stream + Unpin + ?Sized,
+{
+    type Item = (usize, S::Item);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        for i in 0..N {
+            let index = (this.next + i) % N;
+            if this.terminated[index] {
+                continue;
+            }
+            match Pin::new(&mut *this.streams[index]).poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    this.next = (index + 1) % N;
+                    return Poll::Ready(Some((index, item)));
+                }
+                Poll::Ready(None) => this.terminated[index] = true,
+                Poll::Pending => {}
+            }
+        }
+
+        if this.is_terminated() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<S, const N: usize> FusedStream for MergeTagged<'_, S, N>
+where
+    S: Stream + Unpin + ?Sized,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated.iter().all(|&terminated| terminated)
+    }
+}
+
+/// A special USART mode.
+///
+/// USART1, USART2 and USART3 support all of these modes.
+/// On high-density devices, UART4 and UART5 support IrDA but not smartcard mode.
+/// The DMA data path is the same in all modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Normal asynchronous mode.
+    Normal,
+    /// IrDA SIR mode.
+    ///
+    /// In normal power mode, the prescaler must be 1 (RM0008 27.3.12).
+    /// In low-power mode, the prescaler divides the peripheral clock down to the low-power frequency of nominally 1.8432 MHz, which determines the pulse width.
+    /// [`Mode::irda_low_power`] derives it from the peripheral clock.
+    IrDA {
+        /// Whether to use the low-power mode.
+        low_power: bool,
+        /// The prescaler, which must be 1 in normal power mode and must not be 0 in low-power mode.
+        prescaler: u8,
+    },
+    /// Smartcard mode, using 1.5 stop bits and the USART clock output.
+    ///
+    /// This sets the word length to 9 bits (M = 1) with even parity, as required for smartcard mode (RM0008 27.3.11).
+    /// These are not restored when switching to another mode afterwards.
+    Smartcard {
+        /// The prescaler dividing the peripheral clock by `2 * prescaler` for the smartcard clock.
+        prescaler: u8,
+        /// The guard time in baud clocks.
+        guard_time: u8,
+    },
+    /// Loopback via the single-wire half-duplex mode, e.g. for a power-on self-test of the DMA path.
+    ///
+    /// In half-duplex mode, the receiver is internally connected to the TX line, so the USART receives the bytes it sends.
+    /// The F1 has no dedicated loopback mode, so the bytes are still driven on the TX pin and the RX pin is ignored.
+    /// A device driving the TX line, or a TX pin not configured as alternate function, corrupts the self-test.
+    Loopback,
+}
+
+impl Mode {
+    /// The nominal low-power frequency of IrDA SIR mode.
+    const IRDA_LOW_POWER_FREQ: u32 = 1_843_200;
+
+    /// Creates a low-power IrDA mode with the prescaler derived from `pclk`, the clock of the USART's peripheral bus.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pclk` can't be divided down to the low-power frequency of 1.8432 MHz by a prescaler of 1 to 255.
+    pub fn irda_low_power(pclk: Hz) -> Self {
+        let prescaler = (pclk.0 + Self::IRDA_LOW_POWER_FREQ / 2) / Self::IRDA_LOW_POWER_FREQ;
+        assert!(
+            (1..=u32::from(u8::MAX)).contains(&prescaler),
+            "peripheral clock is out of range for low-power IrDA"
+        );
+        Mode::IrDA {
+            low_power: true,
+            // Unwrapping: The prescaler has been checked above
+            prescaler: u8::try_from(prescaler).unwrap(),
+        }
+    }
+}
+
+/// An extension trait for enabling special USART modes before wrapping the serial in a [`TxSink`] or an [`RxStream`].
+///
+/// # Examples
+///
+/// ```
+/// let mut serial = Serial::usart3(dp.USART3, (tx, rx), &mut mapr, config, clocks, &mut apb1);
+/// serial.set_mode(Mode::IrDA { low_power: false, prescaler: 1 });
+/// let (tx, rx) = serial.split();
+/// ```
+pub trait ModeExt {
+    /// Configures the USART for `mode`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prescaler of [`Mode::IrDA`] is not 1 in normal power mode or is 0 in low-power mode.
+    fn set_mode(&mut self, mode: Mode);
+}
+
+macro_rules! mode {
+    ($($USARTX:ident,)+) => {
+        $(
+            impl<PINS> ModeExt for Serial<$USARTX, PINS> {
+                fn set_mode(&mut self, mode: Mode) {
+                    if let Mode::IrDA { low_power, prescaler } = mode {
+                        if low_power {
+                            assert_ne!(prescaler, 0, "low-power IrDA requires a non-zero prescaler");
+                        } else {
+                            assert_eq!(prescaler, 1, "normal power IrDA requires a prescaler of 1");
+                        }
+                    }
+                    // Safety: We own the Serial for this peripheral
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    // The mode may only be changed while the USART is disabled
+                    usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    usart.cr3.modify(|_, w| {
+                        w.iren().clear_bit().irlp().clear_bit().scen().clear_bit().hdsel().clear_bit()
+                    });
+                    usart.cr2.modify(|_, w| w.clken().clear_bit());
+                    match mode {
+                        Mode::Normal => {}
+                        Mode::IrDA { low_power, prescaler } => {
+                            // Safety: The prescaler has been checked above
+                            usart.gtpr.write(|w| unsafe { w.bits(u32::from(prescaler)) });
+                            usart.cr3.modify(|_, w| w.iren().set_bit().irlp().bit(low_power));
+                        }
+                        Mode::Smartcard { prescaler, guard_time } => {
+                            // Safety: Any prescaler and guard time are valid
+                            usart.gtpr.write(|w| unsafe {
+                                w.bits(u32::from(guard_time) << 8 | u32::from(prescaler))
+                            });
+                            // 8 data bits and an even parity bit
+                            usart.cr1.modify(|_, w| w.m().set_bit().pce().set_bit().ps().clear_bit());
+                            usart.cr2.modify(|_, w| w.stop().bits(0b11).clken().set_bit());
+                            usart.cr3.modify(|_, w| w.scen().set_bit());
+                        }
+                        Mode::Loopback => {
+                            // Half-duplex mode requires LINEN to be cleared, which the HAL never sets
+                            usart.cr3.modify(|_, w| w.hdsel().set_bit());
+                        }
+                    }
+                    usart.cr1.modify(|_, w| w.ue().set_bit());
+                }
+            }
+        )+
+    }
+}
+
+mode!(USART1, USART2, USART3,);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rts_hysteresis() {
+        // Halves of 32 bytes, deasserting at 48 and reasserting at 16 bytes
+        assert!(!rts_paused(false, 40, 32, 48, 16));
+        assert!(rts_paused(false, 48, 32, 48, 16));
+        assert!(rts_paused(true, 40, 32, 48, 16));
+        assert!(!rts_paused(true, 16, 32, 48, 16));
+    }
+
+    #[test]
+    fn rts_reasserted_after_overshoot() {
+        // The sender has overshot to 60 bytes, and yielding the completed half leaves 28 bytes in the half being filled
+        assert!(rts_paused(false, 60, 32, 48, 16));
+        assert!(!rts_paused(true, 28, 32, 48, 16));
+    }
+
+    #[test]
+    fn rts_not_deasserted_without_completed_half() {
+        assert!(!rts_paused(false, 24, 32, 16, 8));
+    }
+
+    #[test]
+    fn transfer_future_pending_after_ready() {
+        // Returning Ready takes the transfer, so this is the state after completion
+        let mut transfer = TransferFuture::<Transfer<R, &'static mut [u8], TxDma3>>(None);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(Pin::new(&mut transfer).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut transfer).poll(&mut cx).is_pending());
+        assert!(transfer.is_terminated());
+    }
+
+    #[test]
+    fn irda_low_power_prescaler() {
+        // 36 MHz on APB1 and 8 MHz without PLL
+        assert_eq!(
+            Mode::irda_low_power(Hz(36_000_000)),
+            Mode::IrDA {
+                low_power: true,
+                prescaler: 20
+            }
+        );
+        assert_eq!(
+            Mode::irda_low_power(Hz(8_000_000)),
+            Mode::IrDA {
+                low_power: true,
+                prescaler: 4
+            }
+        );
+    }
+}
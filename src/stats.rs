@@ -0,0 +1,107 @@
+//! [`Stream`] adapters for statistics over samples, such as sensor readings.
+
+use core::{
+    convert::TryFrom,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures::{ready, stream::Stream};
+use heapless::HistoryBuffer;
+
+/// Statistics over a window of samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats<T> {
+    /// The mean of the samples, rounded towards zero.
+    pub mean: T,
+    /// The smallest sample.
+    pub min: T,
+    /// The largest sample.
+    pub max: T,
+    /// The number of samples in the window.
+    pub len: usize,
+}
+
+/// An extension trait for [`Stream`]s of samples that provides windowed statistics.
+pub trait StatsExt: Stream + Sized {
+    /// Yields the [`Stats`] over the last `N` samples for each sample.
+    ///
+    /// The window is a ring buffer of `N` samples, so no allocation is needed.
+    /// The first `N - 1` samples yield stats over the partial window, unless configured otherwise with [`WindowedStats::skip_partial`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut stats = samples.windowed_stats::<16>();
+    /// while let Some(stats) = stats.next().await {
+    ///     defmt::info!("mean: {:u16}", stats.mean);
+    /// }
+    /// ```
+    fn windowed_stats<const N: usize>(self) -> WindowedStats<Self, N>
+    where
+        Self::Item: Copy,
+    {
+        WindowedStats {
+            stream: self,
+            window: HistoryBuffer::new(),
+            skip_partial: false,
+        }
+    }
+}
+
+impl<S: Stream> StatsExt for S {}
+
+/// Stream for the [`windowed_stats`](StatsExt::windowed_stats) method.
+#[must_use = "streams do nothing unless polled"]
+pub struct WindowedStats<S: Stream, const N: usize> {
+    stream: S,
+    window: HistoryBuffer<S::Item, N>,
+    skip_partial: bool,
+}
+
+impl<S: Stream, const N: usize> WindowedStats<S, N> {
+    /// Configures whether to suppress the stats of the first `N - 1` samples, which only cover a partial window.
+    pub fn skip_partial(mut self, skip_partial: bool) -> Self {
+        self.skip_partial = skip_partial;
+        self
+    }
+
+    /// Releases the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, T, const N: usize> Stream for WindowedStats<S, N>
+where
+    S: Stream<Item = T> + Unpin,
+    T: Copy + Ord + Into<i64> + TryFrom<i64> + Unpin,
+{
+    type Item = Stats<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let sample = match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(sample) => sample,
+                None => return Poll::Ready(None),
+            };
+            this.window.write(sample);
+
+            let samples = this.window.as_slice();
+            if this.skip_partial && samples.len() < N {
+                continue;
+            }
+
+            let sum: i64 = samples.iter().map(|&sample| sample.into()).sum();
+            let mean = sum / samples.len() as i64;
+            let stats = Stats {
+                // The mean lies between the samples, so it is representable
+                mean: T::try_from(mean).ok().unwrap(),
+                min: *samples.iter().min().unwrap(),
+                max: *samples.iter().max().unwrap(),
+                len: samples.len(),
+            };
+            return Poll::Ready(Some(stats));
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Asynchronous synchronization primitives for tasks.
+//!
+//! These primitives can be shared between tasks running on the same executor, but not with interrupt handlers.
+//! The exception is [`Notify`], which may also be signalled from interrupt handlers.
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    future::Future,
+    mem,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use futures::{
+    future,
+    stream::{Stream, StreamExt, TakeUntil},
+    task::AtomicWaker,
+};
+use heapless::Vec;
+
+/// The number of tasks which can wait on a primitive without polling repeatedly.
+const WAITERS: usize = 8;
+
+/// A list of the [`Waker`]s of the tasks waiting on a primitive.
+///
+/// Up to [`WAITERS`] wakers are stored, and all of them are woken at once.
+/// Registering does not wake other tasks, so several waiting tasks don't keep each other awake.
+/// If the list is full, the stored wakers are woken and replaced by the registering one.
+/// This discards the wakers of tasks which have stopped waiting, e.g. on a long-lived [`CancelToken`], while tasks still waiting register again.
+struct WakerList(Cell<Vec<Waker, WAITERS>>);
+
+impl WakerList {
+    const fn new() -> Self {
+        Self(Cell::new(Vec::new()))
+    }
+
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.take();
+        if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+            if wakers.is_full() {
+                for waker in mem::take(&mut wakers) {
+                    waker.wake();
+                }
+            }
+            // Unwrapping: The list is not full
+            wakers.push(waker.clone()).ok().unwrap();
+        }
+        self.0.set(wakers);
+    }
+
+    fn wake_all(&self) {
+        for waker in self.0.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// An asynchronous mutual exclusion primitive.
+///
+/// Unlike a [`RefCell`](core::cell::RefCell), contending tasks asynchronously wait for the lock instead of panicking.
+/// When the lock is released, all waiting tasks are woken to contend for it again.
+///
+/// # Examples
+///
+/// ```
+/// static mut X: Mutex<u32> = Mutex::new(0);
+/// *X.lock().await = 42;
+/// ```
+pub struct Mutex<T> {
+    locked: Cell<bool>,
+    wakers: WakerList,
+    value: UnsafeCell<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Creates a new [`Mutex`] in an unlocked state.
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: Cell::new(false),
+            wakers: WakerList::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Attempts to acquire the lock without waiting.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.locked.replace(true) {
+            None
+        } else {
+            Some(MutexGuard(self))
+        }
+    }
+
+    /// Acquires the lock, waiting until it is available.
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        future::poll_fn(|cx| match self.try_lock() {
+            Some(guard) => Poll::Ready(guard),
+            None => {
+                self.wakers.register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Consumes the mutex, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+/// A guard granting exclusive access to the data of a [`Mutex`].
+///
+/// The lock is released when the guard is dropped.
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MutexGuard<'a, T>(&'a Mutex<T>);
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: The guard has exclusive access
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The guard has exclusive access
+        unsafe { &mut *self.0.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.locked.set(false);
+        self.0.wakers.wake_all();
+    }
+}
+
+/// An asynchronous counting semaphore.
+///
+/// Unlike a [`Mutex`], up to a fixed number of tasks may hold a permit at the same time.
+/// This is useful for limiting concurrent DMA transfers.
+/// More tasks than permits may wait at a time, and all of them are woken to contend for a returned permit.
+///
+/// # Examples
+///
+/// ```
+/// static mut DMA_PERMITS: Semaphore = Semaphore::new(2);
+/// let _permit = DMA_PERMITS.acquire().await;
+/// tx_sink.send(buf).await?;
+/// ```
+pub struct Semaphore {
+    permits: Cell<usize>,
+    wakers: WakerList,
+}
+
+impl Semaphore {
+    /// Creates a new [`Semaphore`] with the given number of permits.
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: Cell::new(permits),
+            wakers: WakerList::new(),
+        }
+    }
+
+    /// Returns the number of permits currently available.
+    pub fn available_permits(&self) -> usize {
+        self.permits.get()
+    }
+
+    /// Attempts to acquire a permit without waiting.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        match self.permits.get() {
+            0 => None,
+            permits => {
+                self.permits.set(permits - 1);
+                Some(SemaphorePermit(self))
+            }
+        }
+    }
+
+    /// Acquires a permit, waiting until one is available.
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        future::poll_fn(|cx| match self.try_acquire() {
+            Some(permit) => Poll::Ready(permit),
+            None => {
+                self.wakers.register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// A permit of a [`Semaphore`].
+///
+/// The permit is returned to the semaphore when the guard is dropped.
+#[must_use = "if unused the permit will immediately be returned"]
+pub struct SemaphorePermit<'a>(&'a Semaphore);
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.0.permits.set(self.0.permits.get() + 1);
+        self.0.wakers.wake_all();
+    }
+}
+
+/// A single-slot notification for waking a waiting task, e.g. from an interrupt handler.
+///
+/// A notification is stored until it is consumed by a waiting task.
+/// Several notifications before the task has been polled are coalesced into one.
+/// Only one task should wait at a time, as a second waiter replaces the first one.
+///
+/// Unlike the other primitives of this module, [`Notify`] is [`Sync`] and can be signalled from any priority.
+///
+/// # Examples
+///
+/// ```
+/// static BUTTON: Notify = Notify::new();
+///
+/// #[interrupt]
+/// fn EXTI0() {
+///     // Clear the EXTI pending bit
+///     BUTTON.notify();
+/// }
+///
+/// // In a task
+/// loop {
+///     BUTTON.notified().await;
+///     led.toggle();
+/// }
+/// ```
+pub struct Notify {
+    notified: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl Notify {
+    /// Creates a new [`Notify`] without a stored notification.
+    pub const fn new() -> Self {
+        Self {
+            notified: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Stores a notification, waking the waiting task.
+    pub fn notify(&self) {
+        self.notified.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Consumes a stored notification without waiting, returning whether there was one.
+    pub fn try_take(&self) -> bool {
+        self.notified.swap(false, Ordering::Acquire)
+    }
+
+    /// Creates a [`Future`] that resolves once a notification has been stored, consuming it.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified(self)
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [`Future`] returned by [`Notify::notified`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Notified<'a>(&'a Notify);
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.0.try_take() {
+            return Poll::Ready(());
+        }
+        self.0.waker.register(cx.waker());
+        // Check again, as the notification may have been stored before registering
+        if self.0.try_take() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A shared flag for cooperatively cancelling tasks.
+///
+/// All waiting tasks are woken when the token is cancelled, which can be combined with other futures and streams.
+/// A cancelled token stays cancelled.
+///
+/// # Examples
+///
+/// ```
+/// static mut SHUTDOWN: CancelToken = CancelToken::new();
+/// let mut rx_stream = rx_stream.take_until_cancel(&SHUTDOWN);
+/// while let Some(half) = rx_stream.next().await {
+///     // ...
+/// }
+/// ```
+pub struct CancelToken {
+    cancelled: Cell<bool>,
+    wakers: WakerList,
+}
+
+impl CancelToken {
+    /// Creates a new [`CancelToken`] which has not been cancelled.
+    pub const fn new() -> Self {
+        Self {
+            cancelled: Cell::new(false),
+            wakers: WakerList::new(),
+        }
+    }
+
+    /// Cancels the token, waking all waiting tasks.
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+        self.wakers.wake_all();
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+
+    /// Creates a [`Future`] that resolves once the token has been cancelled.
+    pub fn cancelled(&self) -> WaitForCancel<'_> {
+        WaitForCancel(self)
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [`Future`] returned by [`CancelToken::cancelled`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct WaitForCancel<'a>(&'a CancelToken);
+
+impl Future for WaitForCancel<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.0.is_cancelled() {
+            Poll::Ready(())
+        } else {
+            self.0.wakers.register(cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+/// The error returned by operations which have been cancelled via a [`CancelToken`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+/// An extension trait for [`Stream`]s that provides cancellation via a [`CancelToken`].
+pub trait CancelStreamExt: Stream + Sized {
+    /// Creates a [`Stream`] which finishes once `token` has been cancelled.
+    ///
+    /// A pending item is dropped on cancellation.
+    fn take_until_cancel(self, token: &CancelToken) -> TakeUntil<Self, WaitForCancel<'_>> {
+        self.take_until(token.cancelled())
+    }
+}
+
+impl<S: Stream> CancelStreamExt for S {}
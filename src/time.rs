@@ -0,0 +1,337 @@
+//! Delays using a global timer, without passing an [`AsyncTimer`] around.
+//!
+//! The global timer is installed once by [`init`].
+//! Afterwards, any task can delay itself via [`sleep`] and bound futures via [`timeout`].
+//!
+//! The delays of different tasks share the global timer, which counts down to the earliest deadline.
+//! Up to [`SLEEPERS`] tasks can sleep at the same time.
+//! Further tasks wait for one of them to wake up before starting their own delay.
+//!
+//! # Examples
+//!
+//! ```
+//! static mut TIMER: Option<AsyncTimer<CountDownTimer<TIM2>>> = None;
+//! let timer = unsafe { TIMER.insert(Timer::tim2(dp.TIM2, &clocks, &mut apb1).into()) };
+//! unsafe { time::init(timer) };
+//!
+//! // In any task
+//! time::sleep(Ticks(72_000)).await.unwrap();
+//! ```
+
+use crate::{
+    sync::Semaphore,
+    timer::{self, sealed::Sealed, AsyncTimer, Instance, Ticks, TimerError},
+};
+use core::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+use futures::future::{self, Either};
+use heapless::Vec;
+
+/// The number of tasks which can sleep at the same time.
+pub const SLEEPERS: usize = 8;
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static mut QUEUE: RefCell<Option<Queue>> = RefCell::new(None);
+static mut SLOTS: Semaphore = Semaphore::new(SLEEPERS);
+
+/// A task waiting for its deadline in the [`Queue`].
+struct Sleeper {
+    id: u32,
+    deadline: u64,
+    waker: Waker,
+}
+
+/// The deadlines of the sleeping tasks, which share the global timer.
+///
+/// Times are input clock cycles of the timer, which only advance while it is counting down.
+/// The timer counts down to the earliest deadline and wakes its task.
+/// Each poll of a [`Sleep`] wakes all tasks whose deadline has passed and counts down to the next deadline.
+struct Queue {
+    timer: &'static mut dyn Sealed,
+    /// The time at which the current period of the count down has started.
+    start: u64,
+    /// Whether the timer is counting down.
+    running: bool,
+    sleepers: Vec<Sleeper, SLEEPERS>,
+    next_id: u32,
+}
+
+impl Queue {
+    fn new(timer: &'static mut dyn Sealed) -> Self {
+        timer.listen_update();
+        Self {
+            timer,
+            start: 0,
+            running: false,
+            sleepers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Accounts for a pending update event, returning whether there has been one.
+    fn advance(&mut self) -> bool {
+        let update = self.timer.take_update();
+        if update {
+            // The count down has restarted with the same period
+            self.start += u64::from(self.timer.period_ticks());
+        }
+        update
+    }
+
+    /// Returns the current time.
+    fn now(&mut self) -> u64 {
+        if !self.running {
+            return self.start;
+        }
+        loop {
+            self.advance();
+            let elapsed = self.timer.elapsed_ticks();
+            // An update event after advancing has reset the elapsed ticks, so they are read again
+            if !self.advance() {
+                return self.start + u64::from(elapsed);
+            }
+        }
+    }
+
+    /// Inserts a sleeper, returning its id.
+    fn insert(&mut self, ticks: u32, waker: &Waker) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let deadline = self.now() + u64::from(ticks);
+        let sleeper = Sleeper {
+            id,
+            deadline,
+            waker: waker.clone(),
+        };
+        // Unwrapping: Each sleeper holds a permit of SLOTS
+        self.sleepers.push(sleeper).ok().unwrap();
+        id
+    }
+
+    /// Removes a sleeper, returning whether it has been in the queue.
+    fn remove(&mut self, id: u32) -> bool {
+        match self.sleepers.iter().position(|sleeper| sleeper.id == id) {
+            Some(index) => {
+                self.sleepers.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wakes the sleepers whose deadline has passed, except `own`, and counts down to the earliest remaining deadline.
+    fn schedule(&mut self, own: Option<u32>) -> Result<(), TimerError> {
+        loop {
+            let now = self.now();
+            for sleeper in &self.sleepers {
+                if sleeper.deadline <= now && Some(sleeper.id) != own {
+                    sleeper.waker.wake_by_ref();
+                }
+            }
+
+            let next = self
+                .sleepers
+                .iter()
+                .filter(|sleeper| sleeper.deadline > now)
+                .min_by_key(|sleeper| sleeper.deadline);
+            let (deadline, waker) = match next {
+                Some(next) => (next.deadline, next.waker.clone()),
+                None => {
+                    if self.running {
+                        self.timer.cancel_update();
+                        self.running = false;
+                        self.start = now;
+                    }
+                    return Ok(());
+                }
+            };
+
+            let end = self.start + u64::from(self.timer.period_ticks());
+            if !self.running || deadline < end {
+                // Deadlines beyond the longest period are reached in several periods
+                let ticks = (deadline - now).min(u64::from(u32::MAX)) as u32;
+                self.timer.start_ticks(ticks);
+                self.start = now;
+                self.running = true;
+            }
+
+            // The timer wakes the task of the earliest deadline, which schedules the next one
+            match self.timer.poll_update(&mut Context::from_waker(&waker)) {
+                Poll::Ready(res) => {
+                    res?;
+                    self.start += u64::from(self.timer.period_ticks());
+                }
+                Poll::Pending => return Ok(()),
+            }
+        }
+    }
+
+    /// Returns whether the deadline of a sleeper has passed.
+    fn expired(&mut self, id: u32) -> bool {
+        let now = self.now();
+        self.sleepers
+            .iter()
+            .find(|sleeper| sleeper.id == id)
+            .map_or(true, |sleeper| sleeper.deadline <= now)
+    }
+}
+
+/// Installs `timer` as the global timer used by [`sleep`] and [`timeout`].
+///
+/// # Safety
+///
+/// [`sleep`] and [`timeout`] must only be called from tasks of one executor, as the global timer is not shared across priorities.
+///
+/// # Panics
+///
+/// Panics if a global timer has already been installed.
+pub unsafe fn init<T>(timer: &'static mut AsyncTimer<T>)
+where
+    T: Instance + 'static,
+{
+    assert!(
+        !INITIALIZED.load(Ordering::Acquire),
+        "time::init has already been called"
+    );
+    *global().borrow_mut() = Some(Queue::new(timer.as_mut()));
+    INITIALIZED.store(true, Ordering::Release);
+}
+
+/// Returns the queue of the global timer.
+///
+/// # Safety
+///
+/// The global timer must only be accessed from one executor.
+unsafe fn global() -> &'static RefCell<Option<Queue>> {
+    &*ptr::addr_of!(QUEUE)
+}
+
+/// Returns the queue of the global timer, which has been installed by [`init`].
+fn queue() -> &'static RefCell<Option<Queue>> {
+    assert!(
+        INITIALIZED.load(Ordering::Acquire),
+        "time::init has not been called"
+    );
+    // Safety: The timer has been installed, so the caller of `init` guarantees that we are on its executor
+    unsafe { global() }
+}
+
+/// Returns the slots of the sleeping tasks.
+///
+/// # Panics
+///
+/// Panics if [`init`] has not been called.
+fn slots() -> &'static Semaphore {
+    queue();
+    // Safety: The timer has been installed, so the caller of `init` guarantees that we are on its executor
+    unsafe { &*ptr::addr_of!(SLOTS) }
+}
+
+/// Delays the current task for the given number of input clock cycles of the global timer.
+///
+/// If [`SLEEPERS`] tasks are already sleeping, the delay only starts once one of them has woken up.
+///
+/// # Panics
+///
+/// Panics if [`init`] has not been called.
+pub async fn sleep<D>(ticks: D) -> Result<(), TimerError>
+where
+    D: Into<Ticks>,
+{
+    let ticks = ticks.into().0;
+    let _permit = slots().acquire().await;
+    Sleep { ticks, id: None }.await
+}
+
+/// The error returned by [`timeout`] if the future has not completed in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Resolves to the output of `future`, unless the given number of input clock cycles of the global timer elapse first.
+///
+/// The timeout occupies one of the [`SLEEPERS`] slots until `future` completes or times out.
+///
+/// # Panics
+///
+/// Panics if [`init`] has not been called.
+pub async fn timeout<D, F>(ticks: D, future: F) -> Result<F::Output, Elapsed>
+where
+    D: Into<Ticks>,
+    F: Future,
+{
+    let sleep = sleep(ticks);
+    futures::pin_mut!(future, sleep);
+    match future::select(future, sleep).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right((res, _)) => {
+            timer::unwrap_infallible(res);
+            Err(Elapsed)
+        }
+    }
+}
+
+/// [`Future`] of a delay on the global timer, which removes its deadline from the queue when dropped.
+struct Sleep {
+    ticks: u32,
+    id: Option<u32>,
+}
+
+impl Future for Sleep {
+    type Output = Result<(), TimerError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut queue = queue().borrow_mut();
+        // Unwrapping: The queue has been installed by init
+        let queue = queue.as_mut().unwrap();
+
+        let id = match this.id {
+            Some(id) => {
+                let sleeper = queue.sleepers.iter_mut().find(|sleeper| sleeper.id == id);
+                if let Some(sleeper) = sleeper {
+                    if !sleeper.waker.will_wake(cx.waker()) {
+                        sleeper.waker = cx.waker().clone();
+                    }
+                }
+                id
+            }
+            None => {
+                let id = queue.insert(this.ticks, cx.waker());
+                this.id = Some(id);
+                id
+            }
+        };
+
+        if let Err(err) = queue.schedule(Some(id)) {
+            queue.remove(id);
+            return Poll::Ready(Err(err));
+        }
+        if queue.expired(id) {
+            queue.remove(id);
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut queue = queue().borrow_mut();
+            // Unwrapping: The queue has been installed by init
+            let queue = queue.as_mut().unwrap();
+            if queue.remove(id) {
+                // The timer may have to wake the task of another deadline instead.
+                // Errors are reported to the remaining sleepers when they are polled.
+                queue.schedule(None).ok();
+            }
+        }
+    }
+}
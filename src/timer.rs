@@ -1,16 +1,166 @@
 //! [`Future`]-based abstractions for timers.
 
+use crate::{
+    nvic,
+    sync::{CancelToken, Cancelled},
+};
 use core::{
+    convert::Infallible,
     future::Future,
+    ops::{Add, Sub},
     pin::Pin,
-    task::{Context, Poll, Waker},
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use cortex_m::asm;
+use embedded_hal::{
+    digital::v2::{OutputPin, PinState, ToggleableOutputPin},
+    timer::CountDown,
+    watchdog::Watchdog,
+    PwmPin,
 };
-use embedded_hal::timer::CountDown;
+use futures::{
+    future::{self, Either, FutureExt},
+    ready,
+    sink::{Sink, SinkExt},
+    stream::Stream,
+};
+#[cfg(feature = "rand_core")]
+use rand_core::RngCore;
+#[cfg(any(feature = "stm32f100", feature = "stm32f103", feature = "connectivity"))]
+use stm32f1xx_hal::pac::TIM1;
+#[cfg(feature = "medium")]
+use stm32f1xx_hal::pac::TIM4;
 use stm32f1xx_hal::{
-    pac::{TIM2, TIM3},
-    time::U32Ext,
+    pac::{Interrupt, TIM2, TIM3},
+    pwm::PwmChannel,
+    rcc::Clocks,
+    time::{Hertz, U32Ext},
     timer::{CountDownTimer, Event, Timer},
 };
+use void::Void;
+
+/// A frequency in hertz.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hz(pub u32);
+
+impl From<Hertz> for Hz {
+    fn from(hertz: Hertz) -> Self {
+        Self(hertz.0)
+    }
+}
+
+impl From<Hz> for Hertz {
+    fn from(hz: Hz) -> Self {
+        Self(hz.0)
+    }
+}
+
+/// A number of cycles of a timer's input clock.
+///
+/// Use [`Instance::clock`] to obtain the input clock of a timer for converting from and to [`Hz`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ticks(pub u32);
+
+impl Ticks {
+    /// Returns the ticks of one period of `freq` on a timer with the input clock `clock`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `freq` is zero.
+    pub const fn from_hz(freq: Hz, clock: Hz) -> Self {
+        assert!(freq.0 != 0, "frequency is zero");
+        Self(clock.0 / freq.0)
+    }
+
+    /// Returns the frequency with a period of these ticks on a timer with the input clock `clock`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if these ticks are zero.
+    pub const fn to_hz(self, clock: Hz) -> Hz {
+        assert!(self.0 != 0, "ticks are zero");
+        Hz(clock.0 / self.0)
+    }
+}
+
+/// A point in time, measured in [`Ticks`] since an arbitrary epoch.
+///
+/// Instants wrap around on overflow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instant(Ticks);
+
+impl Instant {
+    /// Creates an [`Instant`] from the ticks since the epoch.
+    pub const fn from_ticks(ticks: Ticks) -> Self {
+        Self(ticks)
+    }
+
+    /// Returns the ticks since the epoch.
+    pub const fn ticks(self) -> Ticks {
+        self.0
+    }
+}
+
+impl Add<Ticks> for Instant {
+    type Output = Self;
+
+    fn add(self, rhs: Ticks) -> Self::Output {
+        Self(Ticks((self.0).0.wrapping_add(rhs.0)))
+    }
+}
+
+impl Sub for Instant {
+    type Output = Ticks;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Ticks((self.0).0.wrapping_sub((rhs.0).0))
+    }
+}
+
+/// A reusable specification of a delay, independent of a timer.
+///
+/// This allows storing timing constants and passing them between tasks.
+///
+/// # Examples
+///
+/// ```
+/// const DEBOUNCE: DelayConfig = DelayConfig::from_ticks(Ticks(720_000));
+/// timer.delay(DEBOUNCE).await.unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DelayConfig {
+    /// The length of the delay in input clock cycles.
+    pub ticks: Ticks,
+}
+
+impl DelayConfig {
+    /// Creates a [`DelayConfig`] of the given number of input clock cycles.
+    pub const fn from_ticks(ticks: Ticks) -> Self {
+        Self { ticks }
+    }
+
+    /// Creates a [`DelayConfig`] of one period of `freq` on a timer with the input clock `clock`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `freq` is zero.
+    pub const fn from_hz(freq: Hz, clock: Hz) -> Self {
+        Self::from_ticks(Ticks::from_hz(freq, clock))
+    }
+}
+
+impl From<Ticks> for DelayConfig {
+    fn from(ticks: Ticks) -> Self {
+        Self::from_ticks(ticks)
+    }
+}
+
+impl From<DelayConfig> for Ticks {
+    fn from(config: DelayConfig) -> Self {
+        config.ticks
+    }
+}
 
 /// An asynchronous abstraction over a timer.
 ///
@@ -20,11 +170,80 @@ use stm32f1xx_hal::{
 /// let mut timer: AsyncTimer<_> = Timer::tim2(dp.TIM2, &clocks, &mut apb1).into();
 /// loop {
 ///     led.toggle();
-///     timer.delay_for(2.hz()).await;
+///     timer.delay_for(2.hz()).await.unwrap();
 /// }
 /// ```
 pub struct AsyncTimer<T>(T);
 
+#[cfg(feature = "systick")]
+pub(crate) mod systick;
+
+pub(crate) mod sealed {
+    use core::{
+        sync::atomic::{AtomicBool, Ordering},
+        task::{Context, Poll},
+    };
+
+    pub trait Sealed {
+        /// Polls the update event, arming the interrupt if it has not occurred yet.
+        fn poll_update(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), super::TimerError>>;
+
+        /// Masks the interrupt, stops the counter and clears a pending update event.
+        ///
+        /// Stopping the counter keeps it from raising an update event before the next delay is started.
+        fn cancel_update(&mut self);
+
+        /// Clears a pending update event.
+        fn clear_update(&mut self);
+
+        /// Clears a pending update event, returning whether there has been one.
+        fn take_update(&mut self) -> bool;
+
+        /// Restarts the count down with a period of `ticks` input clock cycles.
+        fn start_ticks(&mut self, ticks: u32);
+
+        /// Enables the update interrupt.
+        fn listen_update(&mut self);
+
+        /// Stops the counter and masks the interrupt.
+        fn pause_update(&mut self);
+
+        /// Restarts the counter and unmasks the interrupt.
+        fn resume_update(&mut self);
+
+        /// Returns the period of the count down in input clock cycles.
+        fn period_ticks(&self) -> u32;
+
+        /// Returns the input clock cycles elapsed in the current period of the count down, rounded down to the prescaler.
+        fn elapsed_ticks(&self) -> u32;
+
+        /// Returns the flag marking a pending delay on this timer.
+        fn armed(&self) -> &'static AtomicBool;
+    }
+
+    /// Marks a delay as pending on `timer`.
+    ///
+    /// With debug assertions enabled, this panics if a delay is already pending.
+    /// Arming a second delay would otherwise silently clobber the deadline of the first one, e.g. on a shared timer.
+    pub(crate) fn arm<T: Sealed + ?Sized>(timer: &T) {
+        let armed = timer.armed().swap(true, Ordering::Relaxed);
+        debug_assert!(!armed, "a delay is already pending on this timer");
+    }
+
+    /// Marks the pending delay on `timer` as finished.
+    pub(crate) fn disarm<T: Sealed + ?Sized>(timer: &T) {
+        timer.armed().store(false, Ordering::Relaxed);
+    }
+}
+
+/// A count-down timer that can drive [`Delay`]s.
+///
+/// This trait is implemented for the [`CountDownTimer`]s of all supported timers and can't be implemented outside of this crate.
+pub trait Instance: CountDown + sealed::Sealed {
+    /// Returns the frequency of the timer's input clock.
+    fn clock(clocks: &Clocks) -> Hz;
+}
+
 impl<T> AsMut<T> for AsyncTimer<T> {
     fn as_mut(&mut self) -> &mut T {
         &mut self.0
@@ -33,36 +252,859 @@ impl<T> AsMut<T> for AsyncTimer<T> {
 
 impl<T> AsyncTimer<T>
 where
-    T: CountDown,
+    T: Instance,
 {
     /// Creates a [`Future`] that resolves after the given time has been count down.
     pub fn delay_for<C>(&mut self, count: C) -> Delay<'_, T>
     where
         C: Into<T::Time>,
     {
+        sealed::arm(self.as_mut());
+        self.as_mut().listen_update();
         self.as_mut().start(count);
+        // Discard an update event of a count down started before this timer has been wrapped
+        self.as_mut().clear_update();
+        Delay(&mut self.0)
+    }
+
+    /// Creates a [`Future`] that resolves after the given number of input clock cycles.
+    ///
+    /// Prefer this over [`delay_for`](Self::delay_for), as the units are checked by the compiler.
+    /// Ticks which are not representable by the prescaler are rounded down.
+    /// Delays shorter than 2 ticks last 2 ticks, as the counter of a TIM needs a period of at least 2 ticks.
+    pub fn delay<D>(&mut self, ticks: D) -> Delay<'_, T>
+    where
+        D: Into<Ticks>,
+    {
+        sealed::arm(self.as_mut());
+        self.as_mut().listen_update();
+        self.as_mut().start_ticks(ticks.into().0);
         Delay(&mut self.0)
     }
+
+    /// Creates a [`Future`] that resolves after `ms` milliseconds.
+    ///
+    /// The ticks are computed from the input clock of the timer in `clocks`.
+    /// As with [`delay`](Self::delay), the delay is rounded down to the resolution of the prescaler and lasts at least the shortest delay of the timer, even for `0`.
+    /// Delays not representable by the timer saturate, which is beyond 59 s for a TIM at 72 MHz and beyond 233 ms for SysTick at 72 MHz.
+    pub fn delay_ms(&mut self, ms: u32, clocks: &Clocks) -> Delay<'_, T> {
+        let ticks = u64::from(T::clock(clocks).0) * u64::from(ms) / 1_000;
+        self.delay(Ticks(ticks.min(u64::from(u32::MAX)) as u32))
+    }
+
+    /// Creates a [`Future`] that resolves after `us` microseconds.
+    ///
+    /// The precision and range are the same as for [`delay_ms`](Self::delay_ms).
+    /// Additionally, the delay is rounded down to whole input clock cycles.
+    pub fn delay_us(&mut self, us: u32, clocks: &Clocks) -> Delay<'_, T> {
+        let ticks = u64::from(T::clock(clocks).0) * u64::from(us) / 1_000_000;
+        self.delay(Ticks(ticks.min(u64::from(u32::MAX)) as u32))
+    }
+
+    /// Creates a [`Future`] that resolves after a random number of input clock cycles in `[min, max]`.
+    ///
+    /// This is useful for adding jitter to retry backoffs, so that several nodes do not retry at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` is greater than `max`.
+    #[cfg(feature = "rand_core")]
+    pub fn delay_range<R>(&mut self, min: Ticks, max: Ticks, rng: &mut R) -> Delay<'_, T>
+    where
+        R: RngCore,
+    {
+        assert!(min <= max, "min is greater than max");
+        let span = u64::from(max.0 - min.0) + 1;
+        // Scales the random number into [0, span) by a widening multiplication
+        let offset = ((u64::from(rng.next_u32()) * span) >> 32) as u32;
+        self.delay(Ticks(min.0 + offset))
+    }
+
+    /// Creates a [`Future`] that resolves after the given number of core clock cycles.
+    ///
+    /// Delays shorter than [`BUSY_WAIT_CYCLES`] busy-wait when first polled, avoiding the overhead of arming the interrupt.
+    /// Longer delays are converted to the timer's input clock and use the interrupt.
+    /// This is useful for tiny delays, e.g. between bit-banged operations.
+    pub fn delay_short(&mut self, cycles: u32, clocks: &Clocks) -> ShortDelay<'_, T> {
+        if cycles < BUSY_WAIT_CYCLES {
+            ShortDelay(ShortDelayInner::BusyWait(cycles))
+        } else {
+            let sysclk = Hz::from(clocks.sysclk());
+            let ticks = u64::from(cycles) * u64::from(T::clock(clocks).0) / u64::from(sysclk.0);
+            ShortDelay(ShortDelayInner::Delay(self.delay(Ticks(ticks as u32))))
+        }
+    }
+
+    /// Delays for the given time, unless `token` is cancelled first.
+    ///
+    /// On cancellation, the delay is disarmed.
+    pub async fn delay_or_cancel<C>(
+        &mut self,
+        count: C,
+        token: &CancelToken,
+    ) -> Result<(), Cancelled>
+    where
+        C: Into<T::Time>,
+    {
+        match future::select(self.delay_for(count), token.cancelled()).await {
+            Either::Left((res, _)) => Ok(unwrap_infallible(res)),
+            Either::Right(((), _)) => Err(Cancelled),
+        }
+    }
+
+    /// Runs `body` once per `period`, feeding `watchdog` after each run which completed within its period.
+    ///
+    /// A run has completed in time if `body` has returned before the period, measured from the start of the run, has elapsed.
+    /// The next run starts when the period has elapsed.
+    /// Late runs are not fed for, and a hanging `body` stops feeding altogether, so the watchdog resets the chip instead of the system silently hanging.
+    /// The watchdog's timeout should thus span a few periods.
+    pub async fn supervised_loop<W, C, F, Fut>(
+        &mut self,
+        watchdog: &mut W,
+        period: C,
+        mut body: F,
+    ) -> !
+    where
+        W: Watchdog,
+        C: Into<T::Time> + Clone,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        loop {
+            let mut delay = self.delay_for(period.clone());
+            body().await;
+            if (&mut delay).now_or_never().is_none() {
+                watchdog.feed();
+                unwrap_infallible(delay.await);
+            }
+        }
+    }
+
+    /// Plays the blink `pattern` on `led` in a loop, toggling it after each duration of the pattern.
+    ///
+    /// This is useful for status LEDs, e.g. a heartbeat or an error code.
+    /// An empty pattern holds the current state of the LED.
+    /// Only returns if toggling the LED fails.
+    pub async fn blink_pattern<L, D>(
+        &mut self,
+        led: &mut L,
+        pattern: &[D],
+    ) -> Result<Infallible, L::Error>
+    where
+        L: ToggleableOutputPin,
+        D: Into<Ticks> + Copy,
+    {
+        if pattern.is_empty() {
+            future::pending::<()>().await;
+        }
+        loop {
+            for &duration in pattern {
+                led.toggle()?;
+                unwrap_infallible(self.delay(duration).await);
+            }
+        }
+    }
+
+    /// Blinks `led` by toggling it every `interval` until `until` resolves, leaving it in `state` afterwards.
+    ///
+    /// This is useful for status indication, e.g. blinking an error LED until the user acknowledges.
+    /// Resolves to the output of `until`, unless toggling the LED fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// timer.blink_until(&mut led, Ticks(36_000_000), button.pressed(), PinState::Low).await?;
+    /// ```
+    pub async fn blink_until<L, D, F>(
+        &mut self,
+        led: &mut L,
+        interval: D,
+        until: F,
+        state: PinState,
+    ) -> Result<F::Output, <L as ToggleableOutputPin>::Error>
+    where
+        L: ToggleableOutputPin + OutputPin<Error = <L as ToggleableOutputPin>::Error>,
+        D: Into<Ticks> + Copy,
+        F: Future,
+    {
+        let output = {
+            let pattern = [interval];
+            let blink = self.blink_pattern(led, &pattern);
+            futures::pin_mut!(blink, until);
+            match future::select(blink, until).await {
+                Either::Left((res, _)) => match res? {},
+                Either::Right((output, _)) => output,
+            }
+        };
+        led.set_state(state)?;
+        Ok(output)
+    }
+
+    /// Runs `step` once per `period`, passing the time actually elapsed since the previous step.
+    ///
+    /// The time is read from `now`, e.g. a cycle counter, in the same ticks as `period`.
+    /// Deadlines are scheduled relative to the previous deadline, so the delay does not accumulate the time spent in `step`.
+    /// If a step is late by more than a period, the missed periods are skipped and the elapsed time passed to the next step reflects this.
+    /// This is useful for control loops, such as a PID controller, which need the actual `dt` of each step.
+    pub async fn control_loop<N, F>(&mut self, period: Ticks, mut now: N, mut step: F) -> !
+    where
+        N: FnMut() -> Instant,
+        F: FnMut(Ticks),
+    {
+        let mut last = now();
+        let mut deadline = last + period;
+        loop {
+            let remaining = deadline - now();
+            // The deadline has passed if the wrapping difference is negative
+            if (remaining.0 as i32) > 0 {
+                unwrap_infallible(self.delay(remaining).await);
+            }
+
+            let instant = now();
+            step(instant - last);
+            last = instant;
+
+            deadline = deadline + period;
+            if ((deadline - instant).0 as i32) <= 0 {
+                deadline = instant + period;
+            }
+        }
+    }
+
+    /// Creates a [`Stream`] yielding the elapsed fraction of a delay of `total` input clock cycles.
+    ///
+    /// The fraction is updated `steps` times, evenly spaced, and the stream finishes after yielding `1.0` when the delay has elapsed.
+    /// This is useful for animating a progress indicator during a fixed wait.
+    pub fn delay_progress(&mut self, total: Ticks, steps: u32) -> DelayProgress<'_, T> {
+        let steps = steps.max(1);
+        DelayProgress {
+            delay: self.delay(Ticks(total.0 / steps)),
+            step: 0,
+            steps,
+        }
+    }
+
+    /// Creates a [`Stream`] yielding once per overflow of the counter, which runs continuously with a period of `period` input clock cycles.
+    ///
+    /// Counting the overflows extends the counter in software, e.g. for measuring intervals longer than a period.
+    /// See [`Overflows`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut overflows = timer.overflows(Ticks(0x1_0000));
+    /// let mut high = 0u32;
+    /// while overflows.next().await.is_some() {
+    ///     high += 1;
+    /// }
+    /// ```
+    pub fn overflows<D>(&mut self, period: D) -> Overflows<'_, T>
+    where
+        D: Into<Ticks>,
+    {
+        Overflows(self.delay(period))
+    }
+}
+
+/// An asynchronous abstraction over a PWM channel, applying duty cycles glitch-free at update events.
+///
+/// The duty cycle is written to the preloaded compare register, which is only loaded at the next update event.
+/// The auto-reload register is preloaded as well, so a period is never cut short.
+/// As a [`Sink`] of duty cycles, only one duty cycle is applied per period, which allows forwarding a control stream.
+///
+/// As all channels of a timer share its update event and interrupt, only one [`AsyncPwmOut`] per timer may exist at a time.
+///
+/// # Examples
+///
+/// ```
+/// let (c1, ..) = Timer::tim2(dp.TIM2, &clocks, &mut rcc.apb1).pwm(pins, &mut afio.mapr, 1.khz()).split();
+/// let mut pwm = AsyncPwmOut::new(c1);
+/// pwm.set_duty(pwm.max_duty() / 2).await;
+/// duty_stream.map(Ok).forward(&mut pwm).await.unwrap();
+/// ```
+pub struct AsyncPwmOut<TIM, C> {
+    channel: PwmChannel<TIM, C>,
+    /// Whether a duty cycle has been written which has not been loaded yet.
+    written: bool,
+    _exclusive: Exclusive,
+}
+
+/// Marks the timer of an [`AsyncPwmOut`] as in use until dropped.
+struct Exclusive(&'static AtomicBool);
+
+impl Exclusive {
+    /// Marks the timer of `taken` as in use.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timer is already in use.
+    fn take(taken: &'static AtomicBool) -> Self {
+        assert!(
+            !taken.swap(true, Ordering::Acquire),
+            "timer is already used by another AsyncPwmOut"
+        );
+        Self(taken)
+    }
+}
+
+impl Drop for Exclusive {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<TIM, C> AsyncPwmOut<TIM, C>
+where
+    Self: Sink<u16, Error = Infallible> + Unpin,
+    PwmChannel<TIM, C>: PwmPin<Duty = u16>,
+{
+    /// Sets the duty cycle, resolving once it has been applied at the next update event.
+    pub async fn set_duty(&mut self, duty: u16) {
+        // Unwrapping: AsyncPwmOut is infallible
+        self.send(duty).await.unwrap()
+    }
+
+    /// Returns the maximum duty cycle.
+    pub fn max_duty(&self) -> u16 {
+        self.channel.get_max_duty()
+    }
+
+    /// Releases the PWM channel.
+    pub fn release(self) -> PwmChannel<TIM, C> {
+        self.channel
+    }
+}
+
+/// A free-running timer implementing delays by output compare.
+///
+/// Unlike [`AsyncTimer`], the counter is never reset or reprogrammed for a delay.
+/// Instead, a compare interrupt is scheduled at the current count plus the delay.
+/// This avoids the error introduced by restarting the prescaler, which is useful for back-to-back delays in control loops.
+///
+/// # Examples
+///
+/// ```
+/// let mut timer = CompareTimer::new(Timer::tim2(dp.TIM2, &clocks, &mut apb1), 71);
+/// loop {
+///     control_step();
+///     timer.delay(Ticks(72_000)).await.unwrap();
+/// }
+/// ```
+pub struct CompareTimer<TIM> {
+    tim: TIM,
+    prescaler: u16,
+}
+
+/// [`Future`] returned by [`CompareTimer::delay`].
+///
+/// Dropping a pending [`CompareDelay`] may leave the compare interrupt armed, which results in a spurious wakeup at most.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct CompareDelay<'a, TIM> {
+    timer: &'a mut CompareTimer<TIM>,
+    /// The number of compare matches until the delay has elapsed.
+    matches: u32,
+}
+
+/// An error of a timer.
+///
+/// The timers of the HAL are currently infallible, so this error is never returned.
+/// It keeps the [`Future`]s of this module compatible with HALs reporting timer errors.
+/// Helpers built on these futures, such as [`AsyncTimer::blink_pattern`], rely on this and panic on errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerError;
+
+impl From<Void> for TimerError {
+    fn from(err: Void) -> Self {
+        void::unreachable(err)
+    }
+}
+
+/// Unwraps the result of a delay in helpers which don't report [`TimerError`]s.
+pub(crate) fn unwrap_infallible(res: Result<(), TimerError>) {
+    // Unwrapping: The timers of the HAL are infallible
+    res.unwrap()
 }
 
 /// [`Future`] returned by [`delay_for`].
 ///
+/// Dropping a pending [`Delay`] disarms it, so the next delay on the same timer starts cleanly.
+///
 /// [`delay_for`]: AsyncTimer::delay_for
 #[must_use = "futures do nothing unless you `.await` or poll them"]
-pub struct Delay<'a, T>(&'a mut T);
+pub struct Delay<'a, T: Instance>(&'a mut T);
 
-impl<T> AsMut<T> for Delay<'_, T> {
+impl<T: Instance> AsMut<T> for Delay<'_, T> {
     fn as_mut(&mut self) -> &mut T {
         self.0
     }
 }
 
+impl<T: Instance> Delay<'_, T> {
+    /// Pauses the delay, preserving the remaining count.
+    ///
+    /// The interrupt is masked while paused, so the task is not woken.
+    pub fn pause(&mut self) {
+        self.as_mut().pause_update();
+    }
+
+    /// Resumes a paused delay from where it has been paused.
+    pub fn resume(&mut self) {
+        self.as_mut().resume_update();
+    }
+
+    /// Restarts the delay with the given number of input clock cycles, discarding a pending update event.
+    pub(crate) fn restart(&mut self, ticks: Ticks) {
+        self.as_mut().listen_update();
+        self.as_mut().start_ticks(ticks.0);
+    }
+
+    /// Restarts the delay with the given time, discarding a pending update event.
+    pub(crate) fn restart_for<C>(&mut self, count: C)
+    where
+        C: Into<T::Time>,
+    {
+        self.as_mut().listen_update();
+        self.as_mut().start(count);
+        self.as_mut().clear_update();
+    }
+
+    /// Stops the delay until it is restarted.
+    pub(crate) fn stop(&mut self) {
+        self.as_mut().cancel_update();
+    }
+}
+
+impl<T: Instance> Future for Delay<'_, T> {
+    type Output = Result<(), TimerError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().as_mut().poll_update(cx)
+    }
+}
+
+impl<T: Instance> Drop for Delay<'_, T> {
+    fn drop(&mut self) {
+        self.as_mut().cancel_update();
+        sealed::disarm(self.as_mut());
+    }
+}
+
+/// The number of core clock cycles below which [`AsyncTimer::delay_short`] busy-waits.
+pub const BUSY_WAIT_CYCLES: u32 = 1_000;
+
+/// [`Future`] returned by [`delay_short`].
+///
+/// [`delay_short`]: AsyncTimer::delay_short
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ShortDelay<'a, T: Instance>(ShortDelayInner<'a, T>);
+
+enum ShortDelayInner<'a, T: Instance> {
+    BusyWait(u32),
+    Delay(Delay<'a, T>),
+}
+
+impl<T: Instance> Future for ShortDelay<'_, T> {
+    type Output = Result<(), TimerError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.get_mut().0 {
+            ShortDelayInner::BusyWait(cycles) => {
+                asm::delay(*cycles);
+                Poll::Ready(Ok(()))
+            }
+            ShortDelayInner::Delay(delay) => Pin::new(delay).poll(cx),
+        }
+    }
+}
+
+/// [`Stream`] returned by [`delay_progress`].
+///
+/// If the task is not polled for longer than a step, the missed steps are skipped, so the delay is stretched.
+///
+/// [`delay_progress`]: AsyncTimer::delay_progress
+#[must_use = "streams do nothing unless polled"]
+pub struct DelayProgress<'a, T: Instance> {
+    delay: Delay<'a, T>,
+    step: u32,
+    steps: u32,
+}
+
+impl<T: Instance> Stream for DelayProgress<'_, T> {
+    type Item = f32;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.step == self.steps {
+            return Poll::Ready(None);
+        }
+
+        unwrap_infallible(ready!(Pin::new(&mut self.delay).poll(cx)));
+        // The count down restarts automatically for the next step
+        self.step += 1;
+        Poll::Ready(Some(self.step as f32 / self.steps as f32))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.steps - self.step) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+/// [`Stream`] returned by [`overflows`].
+///
+/// The hardware latches one pending overflow, so an overflow occurring while the task is delayed is still yielded.
+/// The task has to poll the stream at least once per period though, as further overflows before that are merged into the pending one.
+/// The stream never finishes.
+///
+/// [`overflows`]: AsyncTimer::overflows
+#[must_use = "streams do nothing unless polled"]
+pub struct Overflows<'a, T: Instance>(Delay<'a, T>);
+
+impl<T: Instance> Stream for Overflows<'_, T> {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        unwrap_infallible(ready!(Pin::new(&mut self.0).poll(cx)));
+        // The count down restarts automatically for the next period
+        Poll::Ready(Some(()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+/// What a [`Throttle`] does with items arriving during the minimum interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Throttling {
+    /// Discards the items.
+    Drop,
+    /// Leaves the items in the underlying stream, yielding them after the interval.
+    Buffer,
+}
+
+/// An extension trait for throttling [`Stream`]s with an [`AsyncTimer`].
+pub trait ThrottleExt: Stream + Sized {
+    /// Yields items at most once per `min_interval`.
+    ///
+    /// After yielding an item, further items are suppressed until `min_interval` has elapsed, as configured by `throttling`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // At most 10 events per second
+    /// let mut events = events.throttle(&mut timer, 10.hz(), Throttling::Drop);
+    /// ```
+    fn throttle<T, C>(
+        self,
+        timer: &mut AsyncTimer<T>,
+        min_interval: C,
+        throttling: Throttling,
+    ) -> Throttle<'_, Self, T, C>
+    where
+        T: Instance,
+        C: Into<T::Time> + Clone,
+    {
+        let mut delay = timer.delay_for(min_interval.clone());
+        // The delay is started by the first item
+        delay.stop();
+        Throttle {
+            stream: self,
+            delay,
+            min_interval,
+            throttling,
+            waiting: false,
+        }
+    }
+}
+
+impl<S: Stream> ThrottleExt for S {}
+
+/// Stream for the [`throttle`](ThrottleExt::throttle) method.
+#[must_use = "streams do nothing unless polled"]
+pub struct Throttle<'a, S, T: Instance, C> {
+    stream: S,
+    delay: Delay<'a, T>,
+    min_interval: C,
+    throttling: Throttling,
+    waiting: bool,
+}
+
+impl<S, T, C> Stream for Throttle<'_, S, T, C>
+where
+    S: Stream + Unpin,
+    T: Instance,
+    C: Into<T::Time> + Clone,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.waiting {
+            let mut saturated = false;
+            if this.throttling == Throttling::Drop {
+                // Yield to other tasks after a burst of dropped items, as the stream may always be ready
+                saturated = true;
+                for _ in 0..MAX_DROPPED_PER_POLL {
+                    match Pin::new(&mut this.stream).poll_next(cx) {
+                        Poll::Ready(Some(_)) => {}
+                        Poll::Ready(None) => return Poll::Ready(None),
+                        Poll::Pending => {
+                            saturated = false;
+                            break;
+                        }
+                    }
+                }
+            }
+            match Pin::new(&mut this.delay).poll(cx) {
+                Poll::Ready(res) => unwrap_infallible(res),
+                Poll::Pending => {
+                    if saturated {
+                        // The stream has not registered the waker, as it has been ready throughout
+                        cx.waker().wake_by_ref();
+                    }
+                    return Poll::Pending;
+                }
+            }
+            this.delay.stop();
+            this.waiting = false;
+        }
+
+        let item = ready!(Pin::new(&mut this.stream).poll_next(cx));
+        if item.is_some() {
+            this.delay.restart_for(this.min_interval.clone());
+            this.waiting = true;
+        }
+        Poll::Ready(item)
+    }
+}
+
+/// The maximum number of items a [`Throttle`] drops per poll before yielding to other tasks.
+const MAX_DROPPED_PER_POLL: usize = 32;
+
+/// An extension trait for [`Stream`]s that provides timestamping of items.
+pub trait TimestampExt: Stream + Sized {
+    /// Pairs each item with the [`Instant`] returned by `now` when the item has been yielded.
+    ///
+    /// The timestamp is taken when the task polls the item, so it includes the scheduling latency of the task.
+    /// Polling promptly, e.g. from a high priority executor, keeps the timestamp close to the DMA completion.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut frames = rx_stream.timestamped(|| Instant::from_ticks(Ticks(DWT::get_cycle_count())));
+    /// while let Some((instant, half)) = frames.next().await {
+    ///     // ...
+    /// }
+    /// ```
+    fn timestamped<F>(self, now: F) -> Timestamped<Self, F>
+    where
+        F: FnMut() -> Instant,
+    {
+        Timestamped { stream: self, now }
+    }
+}
+
+impl<S: Stream> TimestampExt for S {}
+
+/// [`Stream`] returned by [`timestamped`].
+///
+/// [`timestamped`]: TimestampExt::timestamped
+#[must_use = "streams do nothing unless polled"]
+pub struct Timestamped<S, F> {
+    stream: S,
+    now: F,
+}
+
+impl<S, F> Timestamped<S, F> {
+    /// Releases the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S, F> Stream for Timestamped<S, F>
+where
+    S: Stream + Unpin,
+    F: FnMut() -> Instant + Unpin,
+{
+    type Item = (Instant, S::Item);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let item = ready!(Pin::new(&mut this.stream).poll_next(cx));
+        Poll::Ready(item.map(|item| ((this.now)(), item)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+/// A schedule of timed steps, each waiting for its delay and then running its action.
+///
+/// This is useful for test sequences, such as turning on a relay, waiting 100 ms and pulsing an output.
+///
+/// # Examples
+///
+/// ```
+/// let mut relay_on = || relay.set_high().unwrap();
+/// let mut pulse = || output.toggle().unwrap();
+/// let mut steps: [(Ticks, &mut dyn FnMut()); 2] = [(Ticks(0), &mut relay_on), (Ticks(7_200_000), &mut pulse)];
+/// Sequence::new(&mut steps).looping(true).cancel_on(&STOP).run(&mut timer).await?;
+/// ```
+pub struct Sequence<'a, 'f> {
+    steps: &'a mut [(Ticks, &'f mut dyn FnMut())],
+    looping: bool,
+    token: Option<&'a CancelToken>,
+}
+
+impl<'a, 'f> Sequence<'a, 'f> {
+    /// Creates a new [`Sequence`] running `steps` once.
+    pub fn new(steps: &'a mut [(Ticks, &'f mut dyn FnMut())]) -> Self {
+        Self {
+            steps,
+            looping: false,
+            token: None,
+        }
+    }
+
+    /// Configures whether to restart from the first step after the last one.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Configures aborting the sequence once `token` has been cancelled.
+    pub fn cancel_on(mut self, token: &'a CancelToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Runs the steps on `timer`.
+    ///
+    /// Only returns if the sequence does not loop or has been cancelled.
+    /// On cancellation, the pending delay is disarmed and no further actions are run.
+    pub async fn run<T: Instance>(self, timer: &mut AsyncTimer<T>) -> Result<(), Cancelled> {
+        loop {
+            for (delay, action) in self.steps.iter_mut() {
+                let delay = timer.delay(*delay);
+                match self.token {
+                    Some(token) => match future::select(delay, token.cancelled()).await {
+                        Either::Left((res, _)) => unwrap_infallible(res),
+                        Either::Right(((), _)) => return Err(Cancelled),
+                    },
+                    None => unwrap_infallible(delay.await),
+                }
+                action();
+            }
+
+            if !self.looping {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// A snapshot of the deviations of wake times from the scheduled period, see [`JitterMeter`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JitterStats {
+    /// The number of measured periods.
+    pub samples: u32,
+    /// The smallest deviation in ticks, which is negative for early wakes.
+    pub min: i32,
+    /// The largest deviation in ticks.
+    pub max: i32,
+    /// The mean deviation in ticks.
+    pub mean: i32,
+}
+
+/// A diagnostic measuring the jitter of periodic wakes.
+///
+/// Each call to [`tick`](Self::tick) records the deviation of the time since the previous tick from the scheduled period, read from `now`.
+/// This quantifies the latency introduced by interrupts and the executor, e.g. in a control loop.
+/// The measurement is opt-in, as it only runs where the meter is ticked.
+///
+/// # Examples
+///
+/// ```
+/// let mut meter = JitterMeter::new(period, || Instant::from_ticks(Ticks(DWT::get_cycle_count())));
+/// for _ in 0..1000 {
+///     timer.delay(period).await.unwrap();
+///     meter.tick();
+/// }
+/// let stats = meter.jitter_stats();
+/// ```
+pub struct JitterMeter<F> {
+    period: Ticks,
+    now: F,
+    last: Option<Instant>,
+    stats: JitterStats,
+    sum: i64,
+}
+
+impl<F> JitterMeter<F>
+where
+    F: FnMut() -> Instant,
+{
+    /// Creates a new [`JitterMeter`] for wakes scheduled every `period`.
+    pub fn new(period: Ticks, now: F) -> Self {
+        Self {
+            period,
+            now,
+            last: None,
+            stats: JitterStats::default(),
+            sum: 0,
+        }
+    }
+
+    /// Records a wake.
+    ///
+    /// The first wake only starts the measurement.
+    pub fn tick(&mut self) {
+        let instant = (self.now)();
+        if let Some(last) = self.last.replace(instant) {
+            let deviation = (instant - last).0.wrapping_sub(self.period.0) as i32;
+            let stats = &mut self.stats;
+            if stats.samples == 0 {
+                stats.min = deviation;
+                stats.max = deviation;
+            } else {
+                stats.min = stats.min.min(deviation);
+                stats.max = stats.max.max(deviation);
+            }
+            stats.samples += 1;
+            self.sum += i64::from(deviation);
+            stats.mean = (self.sum / i64::from(stats.samples)) as i32;
+        }
+    }
+
+    /// Returns a snapshot of the measured jitter.
+    pub fn jitter_stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    /// Discards the measurements, starting over with the next wake.
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.stats = JitterStats::default();
+        self.sum = 0;
+    }
+}
+
 macro_rules! timer {
     ($(
-        $TIMX:ident
-    ),+) => {
+        $TIMX:ident: ($INT:ident, $pclk_tim:ident),
+    )+) => {
         $(
             impl AsyncTimer<CountDownTimer<$TIMX>> {
+                /// Creates a new [`AsyncTimer`] without listening to the update event.
+                ///
+                /// The update interrupt is enabled by the first delay.
+                /// Until then, the timer can be used for other purposes without side effects on the update interrupt.
+                pub fn new_unlistened(timer: Timer<$TIMX>) -> Self {
+                    Self(timer.start_count_down(1.hz()))
+                }
+
                 /// Releases the TIM peripheral
                 pub fn release(self) -> $TIMX {
                     self.0.release()
@@ -71,30 +1113,324 @@ macro_rules! timer {
 
             impl From<Timer<$TIMX>> for AsyncTimer<CountDownTimer<$TIMX>> {
                 fn from(timer: Timer<$TIMX>) -> Self {
-                    let mut count_down_timer = timer.start_count_down(1.hz());
-                    count_down_timer.listen(Event::Update);
-                    Self(count_down_timer)
+                    let mut async_timer = Self::new_unlistened(timer);
+                    async_timer.0.listen(Event::Update);
+                    async_timer
                 }
             }
 
-            impl Future for Delay<'_, CountDownTimer<$TIMX>> {
-                type Output = ();
+            /// Wraps a running [`CountDownTimer`], enabling its update interrupt.
+            ///
+            /// The period of the count down is overwritten by the first delay.
+            impl From<CountDownTimer<$TIMX>> for AsyncTimer<CountDownTimer<$TIMX>> {
+                fn from(mut timer: CountDownTimer<$TIMX>) -> Self {
+                    timer.listen(Event::Update);
+                    Self(timer)
+                }
+            }
 
-                fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            impl sealed::Sealed for CountDownTimer<$TIMX> {
+                fn poll_update(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), TimerError>> {
                     use nb::{Error, Result};
 
-                    match self.get_mut().as_mut().wait() {
-                        Result::Ok(ok) => Poll::Ready(ok),
-                        Result::Err(Error::Other(err)) => void::unreachable(err),
+                    match self.wait() {
+                        Result::Ok(ok) => {
+                            record_poll!(DELAY, Ready);
+                            Poll::Ready(Ok(ok))
+                        }
+                        Result::Err(Error::Other(err)) => {
+                            record_poll!(DELAY, Ready);
+                            Poll::Ready(Err(err.into()))
+                        }
                         Result::Err(Error::WouldBlock) => {
-                            waker_interrupt!($TIMX, cx.waker().clone());
+                            record_poll!(DELAY, Pending, $INT);
+                            waker_interrupt!($INT, cx.waker());
                             Poll::Pending
                         }
                     }
                 }
+
+                fn cancel_update(&mut self) {
+                    nvic::mask(Interrupt::$INT);
+                    // Safety: We own the CountDownTimer for this peripheral
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.clear_update();
+                }
+
+                fn clear_update(&mut self) {
+                    self.clear_update_interrupt_flag();
+                }
+
+                fn take_update(&mut self) -> bool {
+                    self.wait().is_ok()
+                }
+
+                fn start_ticks(&mut self, ticks: u32) {
+                    // Safety: We own the CountDownTimer for this peripheral
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    tim.cnt.reset();
+
+                    let psc = (ticks.saturating_sub(1) / (1 << 16)) as u16;
+                    // An auto-reload value of zero blocks the counter, so the shortest period is 2 ticks
+                    let arr = (ticks / (u32::from(psc) + 1)).saturating_sub(1).max(1) as u16;
+                    tim.psc.write(|w| w.psc().bits(psc));
+                    tim.arr.write(|w| w.arr().bits(arr));
+
+                    // Load the prescaler without raising the update event
+                    tim.cr1.modify(|_, w| w.urs().set_bit());
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.cr1.modify(|_, w| w.urs().clear_bit());
+                    // Discard an update event of the previous period
+                    self.clear_update();
+
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+
+                fn listen_update(&mut self) {
+                    self.listen(Event::Update);
+                }
+
+                fn pause_update(&mut self) {
+                    nvic::mask(Interrupt::$INT);
+                    // Safety: We own the CountDownTimer for this peripheral
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    // The counter and auto-reload values are retained while disabled
+                    tim.cr1.modify(|_, w| w.cen().clear_bit());
+                }
+
+                fn resume_update(&mut self) {
+                    // Safety: We own the CountDownTimer for this peripheral
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+                    // Safety: The waker of the paused delay is still registered
+                    unsafe { nvic::unmask(Interrupt::$INT) }
+                }
+
+                fn period_ticks(&self) -> u32 {
+                    // Safety: This is an atomic read of the prescaler and auto-reload registers
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    (u32::from(tim.psc.read().psc().bits()) + 1) * (u32::from(tim.arr.read().arr().bits()) + 1)
+                }
+
+                fn elapsed_ticks(&self) -> u32 {
+                    // Safety: This is an atomic read of the counter and prescaler registers
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    u32::from(tim.cnt.read().bits() as u16) * (u32::from(tim.psc.read().psc().bits()) + 1)
+                }
+
+                fn armed(&self) -> &'static AtomicBool {
+                    static ARMED: AtomicBool = AtomicBool::new(false);
+                    &ARMED
+                }
+            }
+
+            impl Instance for CountDownTimer<$TIMX> {
+                fn clock(clocks: &Clocks) -> Hz {
+                    clocks.$pclk_tim().into()
+                }
             }
         )+
     }
 }
 
-timer!(TIM2, TIM3);
+timer!(TIM2: (TIM2, pclk1_tim), TIM3: (TIM3, pclk1_tim),);
+
+#[cfg(any(feature = "stm32f100", feature = "stm32f103", feature = "connectivity"))]
+timer!(TIM1: (TIM1_UP, pclk2_tim),);
+
+#[cfg(feature = "medium")]
+timer!(TIM4: (TIM4, pclk1_tim),);
+
+/// Implements [`CompareTimer`] and [`AsyncPwmOut`] for general-purpose timers, which share one interrupt for all events.
+macro_rules! compare_timer {
+    ($($TIMX:ident,)+) => {
+        $(
+            impl CompareTimer<$TIMX> {
+                /// Creates a new [`CompareTimer`] counting at the timer's input clock divided by `prescaler + 1`.
+                pub fn new(timer: Timer<$TIMX>, prescaler: u16) -> Self {
+                    let tim = timer.start_count_down(1.hz()).release();
+                    tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    tim.psc.write(|w| w.psc().bits(prescaler));
+                    tim.arr.write(|w| w.arr().bits(u16::MAX));
+
+                    // Load the prescaler without raising the update event
+                    tim.cr1.modify(|_, w| w.urs().set_bit());
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.cr1.modify(|_, w| w.urs().clear_bit());
+
+                    tim.dier.modify(|_, w| w.cc1ie().set_bit());
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+                    Self { tim, prescaler }
+                }
+
+                /// Releases the TIM peripheral
+                pub fn release(self) -> $TIMX {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.dier.modify(|_, w| w.cc1ie().clear_bit());
+                    self.tim
+                }
+
+                /// Creates a [`Future`] that resolves after the given number of input clock cycles.
+                ///
+                /// Ticks are rounded down to multiples of `prescaler + 1`.
+                pub fn delay<D>(&mut self, ticks: D) -> CompareDelay<'_, $TIMX>
+                where
+                    D: Into<Ticks>,
+                {
+                    let ticks = ticks.into().0 / (u32::from(self.prescaler) + 1);
+                    if ticks == 0 {
+                        return CompareDelay { timer: self, matches: 0 };
+                    }
+
+                    let tim = &self.tim;
+                    let start = tim.cnt.read().bits() as u16;
+                    tim.ccr1.write(|w| w.ccr().bits(start.wrapping_add(ticks as u16)));
+                    tim.sr.modify(|_, w| w.cc1if().clear_bit());
+
+                    // The first match is up to one counter period away, the others are one period apart
+                    let mut matches = (ticks - 1) / (1 << 16) + 1;
+                    let first = ticks - (matches - 1) * (1 << 16);
+                    let elapsed = (tim.cnt.read().bits() as u16).wrapping_sub(start);
+                    if u32::from(elapsed) >= first {
+                        // The first match has occurred while setting up
+                        tim.sr.modify(|_, w| w.cc1if().clear_bit());
+                        matches -= 1;
+                    }
+
+                    CompareDelay { timer: self, matches }
+                }
+
+                /// Drives `pin` high for `width` input clock cycles, e.g. for triggering a camera or strobe.
+                ///
+                /// The width is rounded down to multiples of `prescaler + 1`, which is the resolution of the pulse.
+                /// As the falling edge is driven when the task is polled after the compare interrupt, the pulse is extended by the wakeup latency.
+                /// With an executor polling this task immediately, this amounts to a few microseconds at 72 MHz.
+                /// Interrupts of higher priority and other tasks polled first add to this latency.
+                pub async fn pulse<P, D>(&mut self, pin: &mut P, width: D) -> Result<(), P::Error>
+                where
+                    P: OutputPin,
+                    D: Into<Ticks>,
+                {
+                    pin.set_high()?;
+                    unwrap_infallible(self.delay(width).await);
+                    pin.set_low()
+                }
+            }
+
+            impl Future for CompareDelay<'_, $TIMX> {
+                type Output = Result<(), TimerError>;
+
+                fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                    let this = self.get_mut();
+                    let tim = &this.timer.tim;
+                    if this.matches > 0 && tim.sr.read().cc1if().bit_is_set() {
+                        tim.sr.modify(|_, w| w.cc1if().clear_bit());
+                        this.matches -= 1;
+                    }
+
+                    if this.matches == 0 {
+                        Poll::Ready(Ok(()))
+                    } else {
+                        waker_interrupt!($TIMX, cx.waker());
+                        Poll::Pending
+                    }
+                }
+            }
+
+            impl<C> AsyncPwmOut<$TIMX, C>
+            where
+                PwmChannel<$TIMX, C>: PwmPin<Duty = u16>,
+            {
+                /// Creates a new [`AsyncPwmOut`] from the specified PWM channel.
+                ///
+                /// # Panics
+                ///
+                /// Panics if another [`AsyncPwmOut`] of the same timer exists.
+                pub fn new(mut channel: PwmChannel<$TIMX, C>) -> Self {
+                    static TAKEN: AtomicBool = AtomicBool::new(false);
+                    let exclusive = Exclusive::take(&TAKEN);
+                    // Safety: The channel's PWM owns the timer and has configured it
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    tim.cr1.modify(|_, w| w.arpe().set_bit());
+                    channel.enable();
+                    Self {
+                        channel,
+                        written: false,
+                        _exclusive: exclusive,
+                    }
+                }
+
+                /// Clears the update event, leaving the other rc_w0 flags of the timer untouched.
+                fn clear_update() {
+                    // Safety: The channel's PWM owns the timer
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    // Writing 1 leaves a flag unchanged, so flags set after reading are not lost
+                    tim.sr.write(|w| {
+                        w.cc1if().set_bit()
+                            .cc2if().set_bit()
+                            .cc3if().set_bit()
+                            .cc4if().set_bit()
+                            .tif().set_bit()
+                            .cc1of().set_bit()
+                            .cc2of().set_bit()
+                            .cc3of().set_bit()
+                            .cc4of().set_bit()
+                            .uif().clear_bit()
+                    });
+                }
+            }
+
+            impl<C> Sink<u16> for AsyncPwmOut<$TIMX, C>
+            where
+                C: Unpin,
+                PwmChannel<$TIMX, C>: PwmPin<Duty = u16>,
+            {
+                type Error = Infallible;
+
+                fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                    self.poll_flush(cx)
+                }
+
+                fn start_send(self: Pin<&mut Self>, duty: u16) -> Result<(), Self::Error> {
+                    let this = self.get_mut();
+                    this.channel.set_duty(duty);
+                    // An update before clearing delays the completion by one period at most
+                    Self::clear_update();
+                    this.written = true;
+                    Ok(())
+                }
+
+                fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                    let this = self.get_mut();
+                    if !this.written {
+                        return Poll::Ready(Ok(()));
+                    }
+
+                    // Safety: The channel's PWM owns the timer
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    if tim.sr.read().uif().bit_is_set() {
+                        Self::clear_update();
+                        this.written = false;
+                        Poll::Ready(Ok(()))
+                    } else {
+                        tim.dier.modify(|_, w| w.uie().set_bit());
+                        waker_interrupt!($TIMX, cx.waker());
+                        Poll::Pending
+                    }
+                }
+
+                fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                    self.poll_flush(cx)
+                }
+            }
+
+        )+
+    }
+}
+
+compare_timer!(TIM2, TIM3,);
+
+#[cfg(feature = "medium")]
+compare_timer!(TIM4,);
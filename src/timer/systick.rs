@@ -0,0 +1,171 @@
+//! Support for delays driven by the SysTick exception.
+//!
+//! As the SysTick exception is handled by the core and not by the NVIC, it can't be declared by `waker_interrupts!`.
+//! With the `user-interrupts` feature, it is not declared either, but forwarded via [`isr::on_systick`](crate::isr::on_systick).
+
+use super::{sealed, AsyncTimer, Hz, Instance, TimerError};
+use core::{
+    sync::atomic::AtomicBool,
+    task::{Context, Poll, Waker},
+};
+use cortex_m::{interrupt, peripheral::SYST};
+#[cfg(not(feature = "user-interrupts"))]
+use cortex_m_rt::exception;
+use embedded_hal::timer::CountDown;
+use stm32f1xx_hal::{
+    rcc::Clocks,
+    time::U32Ext,
+    timer::{CountDownTimer, Event, Timer},
+};
+
+/// The enable bit of the SysTick control and status register.
+const SYST_CSR_ENABLE: u32 = 1 << 0;
+
+/// The largest value of the SysTick reload value register.
+const SYST_RVR_MAX: u32 = 0x00ff_ffff;
+
+static mut WAKER: Option<Waker> = None;
+
+#[cfg(not(feature = "user-interrupts"))]
+#[exception]
+fn SysTick() {
+    handle();
+}
+
+pub(crate) fn handle() {
+    // Safety: The lower priority context only accesses WAKER in critical sections
+    if let Some(waker) = unsafe { WAKER.take() } {
+        trace!("SysTick: waking");
+        waker.wake();
+    }
+}
+
+impl AsyncTimer<CountDownTimer<SYST>> {
+    /// Creates a new [`AsyncTimer`] without listening to the SysTick exception.
+    ///
+    /// The exception is enabled by the first delay.
+    /// As the SysTick counter has 24 bits, delays are limited to 2<sup>24</sup> cycles of HCLK.
+    pub fn new_unlistened(timer: Timer<SYST>) -> Self {
+        // The period needs to fit into 24 bits, even at the highest HCLK
+        Self(timer.start_count_down(1.khz()))
+    }
+
+    /// Releases the SYST peripheral
+    pub fn release(self) -> SYST {
+        self.0.release()
+    }
+}
+
+impl From<Timer<SYST>> for AsyncTimer<CountDownTimer<SYST>> {
+    fn from(timer: Timer<SYST>) -> Self {
+        let mut async_timer = Self::new_unlistened(timer);
+        async_timer.0.listen(Event::Update);
+        async_timer
+    }
+}
+
+/// Wraps a running [`CountDownTimer`], enabling the SysTick exception.
+///
+/// The period of the count down is overwritten by the first delay.
+impl From<CountDownTimer<SYST>> for AsyncTimer<CountDownTimer<SYST>> {
+    fn from(mut timer: CountDownTimer<SYST>) -> Self {
+        timer.listen(Event::Update);
+        Self(timer)
+    }
+}
+
+impl sealed::Sealed for CountDownTimer<SYST> {
+    fn poll_update(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), TimerError>> {
+        use nb::{Error, Result};
+
+        // The wrap can't be missed, as the exception is pended, but not taken, within the critical section
+        interrupt::free(|_| match self.wait() {
+            Result::Ok(ok) => {
+                record_poll!(DELAY, Ready);
+                Poll::Ready(Ok(ok))
+            }
+            Result::Err(Error::Other(err)) => {
+                record_poll!(DELAY, Ready);
+                Poll::Ready(Err(err.into()))
+            }
+            Result::Err(Error::WouldBlock) => {
+                trace!("SysTick: arming");
+                // Safety: The exception is not taken within the critical section
+                unsafe { WAKER = Some(cx.waker().clone()) }
+                Poll::Pending
+            }
+        })
+    }
+
+    fn cancel_update(&mut self) {
+        // Safety: The exception is not taken within the critical section
+        interrupt::free(|_| unsafe { WAKER = None });
+        // Safety: We own the CountDownTimer for this peripheral
+        let syst = unsafe { &*SYST::ptr() };
+        unsafe { syst.csr.modify(|csr| csr & !SYST_CSR_ENABLE) }
+        self.clear_update();
+    }
+
+    fn clear_update(&mut self) {
+        // Reading the count flag clears it
+        self.wait().ok();
+    }
+
+    fn take_update(&mut self) -> bool {
+        self.wait().is_ok()
+    }
+
+    fn start_ticks(&mut self, ticks: u32) {
+        // Safety: We own the CountDownTimer for this peripheral
+        let syst = unsafe { &*SYST::ptr() };
+        // Safety: The reload value is limited to 24 bits, and writing the current value clears it and the count flag
+        unsafe {
+            // A reload value of zero disables the counter, so the shortest period is 2 ticks
+            syst.rvr
+                .write(ticks.saturating_sub(1).clamp(1, SYST_RVR_MAX));
+            syst.cvr.write(0);
+            // The counter has been stopped if the previous delay has been cancelled
+            syst.csr.modify(|csr| csr | SYST_CSR_ENABLE);
+        }
+    }
+
+    fn listen_update(&mut self) {
+        self.listen(Event::Update);
+    }
+
+    fn pause_update(&mut self) {
+        // Safety: We own the CountDownTimer for this peripheral
+        let syst = unsafe { &*SYST::ptr() };
+        // The current value is retained while disabled
+        unsafe { syst.csr.modify(|csr| csr & !SYST_CSR_ENABLE) }
+    }
+
+    fn resume_update(&mut self) {
+        // Safety: We own the CountDownTimer for this peripheral
+        let syst = unsafe { &*SYST::ptr() };
+        unsafe { syst.csr.modify(|csr| csr | SYST_CSR_ENABLE) }
+    }
+
+    fn period_ticks(&self) -> u32 {
+        // Safety: This is an atomic read of the reload value register
+        unsafe { (*SYST::ptr()).rvr.read() + 1 }
+    }
+
+    fn elapsed_ticks(&self) -> u32 {
+        // Safety: This is an atomic read of the reload and current value registers
+        let syst = unsafe { &*SYST::ptr() };
+        // The counter counts down from the reload value
+        syst.rvr.read() - syst.cvr.read()
+    }
+
+    fn armed(&self) -> &'static AtomicBool {
+        static ARMED: AtomicBool = AtomicBool::new(false);
+        &ARMED
+    }
+}
+
+impl Instance for CountDownTimer<SYST> {
+    fn clock(clocks: &Clocks) -> Hz {
+        clocks.hclk().into()
+    }
+}